@@ -0,0 +1,8 @@
+ALTER TABLE api_keys
+    ADD COLUMN write_access BOOLEAN NOT NULL DEFAULT 'f';
+UPDATE api_keys
+SET write_access = 't'
+WHERE 'upload' = ANY(scopes) OR 'admin' = ANY(scopes);
+ALTER TABLE api_keys
+    ALTER COLUMN write_access DROP DEFAULT,
+    DROP COLUMN scopes;
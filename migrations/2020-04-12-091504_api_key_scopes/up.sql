@@ -0,0 +1,8 @@
+ALTER TABLE api_keys
+    ADD COLUMN scopes TEXT[] NOT NULL DEFAULT '{read}';
+UPDATE api_keys
+SET scopes = '{read,upload,review}'
+WHERE write_access;
+ALTER TABLE api_keys
+    ALTER COLUMN scopes DROP DEFAULT,
+    DROP COLUMN write_access;
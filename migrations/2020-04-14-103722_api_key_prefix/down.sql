@@ -0,0 +1,5 @@
+DELETE FROM api_keys
+WHERE prefix IS NOT NULL;
+DROP INDEX api_key_prefix_idx;
+ALTER TABLE api_keys
+    DROP COLUMN prefix;
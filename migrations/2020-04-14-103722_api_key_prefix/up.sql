@@ -0,0 +1,3 @@
+ALTER TABLE api_keys
+    ADD COLUMN prefix TEXT;
+CREATE UNIQUE INDEX api_key_prefix_idx ON api_keys (prefix);
@@ -0,0 +1,6 @@
+ALTER TABLE api_keys
+    DROP COLUMN created_at,
+    DROP COLUMN expires_at,
+    DROP COLUMN last_used_at,
+    DROP COLUMN revoked_at,
+    DROP COLUMN use_count;
@@ -0,0 +1,8 @@
+ALTER TABLE api_keys
+    ADD COLUMN daily_quota INTEGER;
+CREATE TABLE api_key_usage (
+    key_id UUID NOT NULL REFERENCES api_keys (id) ON DELETE CASCADE,
+    day DATE NOT NULL,
+    requests INTEGER NOT NULL DEFAULT 0,
+    PRIMARY KEY (key_id, day)
+);
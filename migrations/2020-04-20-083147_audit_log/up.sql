@@ -0,0 +1,13 @@
+CREATE TABLE audit_log (
+    id BIGSERIAL PRIMARY KEY,
+    key_id UUID REFERENCES api_keys (id) ON DELETE SET NULL,
+    endpoint TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    question_ids INTEGER[] NOT NULL,
+    answer_rows INTEGER NOT NULL,
+    string_rows INTEGER NOT NULL,
+    client_ip TEXT
+);
+CREATE INDEX audit_log_key_id_index ON audit_log (key_id);
+CREATE INDEX audit_log_created_at_index ON audit_log (created_at);
+CREATE INDEX audit_log_question_ids_index ON audit_log USING GIN (question_ids);
@@ -0,0 +1,6 @@
+ALTER TABLE question_strings
+    DROP COLUMN submitted_by;
+ALTER TABLE answer_strings
+    DROP COLUMN submitted_by;
+ALTER TABLE answers
+    DROP COLUMN submitted_by;
@@ -0,0 +1,9 @@
+ALTER TABLE answers
+    ADD COLUMN submitted_by UUID REFERENCES api_keys (id) ON DELETE SET NULL;
+ALTER TABLE answer_strings
+    ADD COLUMN submitted_by UUID REFERENCES api_keys (id) ON DELETE SET NULL;
+ALTER TABLE question_strings
+    ADD COLUMN submitted_by UUID REFERENCES api_keys (id) ON DELETE SET NULL;
+CREATE INDEX answers_submitted_by_index ON answers (submitted_by);
+CREATE INDEX answer_strings_submitted_by_index ON answer_strings (submitted_by);
+CREATE INDEX question_strings_submitted_by_index ON question_strings (submitted_by);
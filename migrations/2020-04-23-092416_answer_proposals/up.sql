@@ -0,0 +1,10 @@
+CREATE TABLE answer_proposals (
+    id BIGSERIAL PRIMARY KEY,
+    question_id INTEGER NOT NULL REFERENCES answers (question_id) ON DELETE CASCADE,
+    answer_used INTEGER NOT NULL,
+    valid_answers INTEGER[] NOT NULL,
+    test UUID NOT NULL REFERENCES groups (id),
+    submitted_by UUID REFERENCES api_keys (id) ON DELETE SET NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    UNIQUE (question_id, answer_used)
+);
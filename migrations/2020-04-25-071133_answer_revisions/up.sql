@@ -0,0 +1,16 @@
+CREATE TABLE answer_revisions (
+    id BIGSERIAL PRIMARY KEY,
+    question_id INTEGER NOT NULL,
+    old_answer_used INTEGER,
+    new_answer_used INTEGER,
+    old_reviewed BOOLEAN,
+    new_reviewed BOOLEAN,
+    old_valid_answers INTEGER[],
+    new_valid_answers INTEGER[],
+    old_test UUID,
+    new_test UUID,
+    key_id UUID REFERENCES api_keys (id) ON DELETE SET NULL,
+    source TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+CREATE INDEX answer_revisions_question_id_index ON answer_revisions (question_id);
@@ -0,0 +1,6 @@
+UPDATE answer_revisions SET source = replace(source, '_', '-')
+    WHERE source IN ('accept_proposal', 'purge_key');
+ALTER TABLE answer_revisions
+    DROP COLUMN actor_kind,
+    DROP COLUMN new_submitted_by,
+    DROP COLUMN old_submitted_by;
@@ -0,0 +1,8 @@
+ALTER TABLE answer_revisions
+    ADD COLUMN old_submitted_by UUID REFERENCES api_keys (id) ON DELETE SET NULL,
+    ADD COLUMN new_submitted_by UUID REFERENCES api_keys (id) ON DELETE SET NULL,
+    ADD COLUMN actor_kind TEXT NOT NULL DEFAULT 'admin'
+        CHECK (actor_kind IN ('api_key', 'admin'));
+UPDATE answer_revisions SET source = replace(source, '-', '_');
+UPDATE answer_revisions SET actor_kind = 'api_key' WHERE source IN ('upload', 'set_reviewed');
+ALTER TABLE answer_revisions ALTER COLUMN actor_kind DROP DEFAULT;
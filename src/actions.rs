@@ -1,6 +1,9 @@
 use crate::models::*;
 use crate::schema::*;
+use blake2::crypto_mac::Mac;
 use blake2::Blake2b;
+use chrono::{DateTime, Duration, Utc};
+use diesel::dsl::{exists, not};
 use diesel::pg::expression::dsl::any;
 use diesel::pg::upsert::excluded;
 use diesel::pg::PgConnection;
@@ -12,6 +15,11 @@ use rand::prelude::*;
 use std::collections::HashMap;
 use uuid::Uuid;
 
+/// Length of the lookup prefix of an API key.
+const KEY_PREFIX_LEN: u64 = 12;
+/// Length of the secret part of an API key.
+const KEY_SECRET_LEN: u64 = 64;
+
 fn generate_api_key(length: u64) -> String {
     static CHARS: Lazy<Vec<char>> = Lazy::new(|| {
         "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789"
@@ -42,49 +50,146 @@ pub fn get_question_string(conn: &PgConnection, id: i32) -> QueryResult<String>
         .question_string)
 }
 
-pub fn upload_call(conn: &PgConnection, data: JsApiUpload) -> QueryResult<()> {
-    let answer_map = data
+/// Records a change made by `actor` through `endpoint`.
+fn write_audit_entry(
+    conn: &PgConnection,
+    actor: &Actor,
+    endpoint: &str,
+    question_ids: Vec<i32>,
+    answer_rows: usize,
+    string_rows: usize,
+) -> QueryResult<()> {
+    let entry = NewAuditEntry {
+        key_id: Some(actor.key_id),
+        endpoint,
+        question_ids,
+        answer_rows: answer_rows as i32,
+        string_rows: string_rows as i32,
+        client_ip: actor.client_ip.as_deref(),
+    };
+    diesel::insert_into(audit_log::table)
+        .values(&entry)
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Pairs rows read before a change with the rows the change returned.
+fn pair_changes(old: Vec<Answer>, new: Vec<Answer>) -> Vec<(Option<Answer>, Option<Answer>)> {
+    let mut old = old
+        .into_iter()
+        .map(|a| (a.question_id, a))
+        .collect::<HashMap<_, _>>();
+    new.into_iter()
+        .map(|new| (old.remove(&new.question_id), Some(new)))
+        .collect()
+}
+
+/// Records the rows of `changes` whose state differs in the revision
+/// history.
+fn write_revisions(
+    conn: &PgConnection,
+    changes: Vec<(Option<Answer>, Option<Answer>)>,
+    key_id: Option<Uuid>,
+    source: &str,
+) -> QueryResult<()> {
+    let revisions = changes
+        .into_iter()
+        .filter(|change| match change {
+            (Some(old), Some(new)) => {
+                old.answer_used != new.answer_used
+                    || old.reviewed != new.reviewed
+                    || old.valid_answers != new.valid_answers
+                    || old.test != new.test
+                    || old.submitted_by != new.submitted_by
+            }
+            (None, None) => false,
+            _ => true,
+        })
+        .map(|(old, new)| NewAnswerRevision {
+            question_id: old.as_ref().or(new.as_ref()).unwrap().question_id,
+            old_answer_used: old.as_ref().map(|a| a.answer_used),
+            new_answer_used: new.as_ref().map(|a| a.answer_used),
+            old_reviewed: old.as_ref().map(|a| a.reviewed),
+            new_reviewed: new.as_ref().map(|a| a.reviewed),
+            old_valid_answers: old.as_ref().map(|a| a.valid_answers.clone()),
+            new_valid_answers: new.as_ref().map(|a| a.valid_answers.clone()),
+            old_test: old.as_ref().map(|a| a.test),
+            new_test: new.as_ref().map(|a| a.test),
+            key_id,
+            source,
+            old_submitted_by: old.as_ref().and_then(|a| a.submitted_by),
+            new_submitted_by: new.as_ref().and_then(|a| a.submitted_by),
+            actor_kind: if key_id.is_some() { "api_key" } else { "admin" },
+        })
+        .collect::<Vec<_>>();
+    if revisions.is_empty() {
+        return Ok(());
+    }
+    diesel::insert_into(answer_revisions::table)
+        .values(&revisions)
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Stores an upload and its audit entry in a single transaction.
+///
+/// Answers that were already reviewed are not changed. Uploads that
+/// disagree with them are stored as proposals instead.
+pub fn upload_call(conn: &PgConnection, data: JsApiUpload, actor: &Actor) -> QueryResult<()> {
+    conn.transaction(|| upload_call_inner(conn, data, actor))
+}
+
+fn upload_call_inner(conn: &PgConnection, data: JsApiUpload, actor: &Actor) -> QueryResult<()> {
+    // Rows are written in key order so that concurrent uploads lock them in
+    // the same order instead of deadlocking.
+    let mut answer_map = data
         .answer_map
         .iter()
         .map(|tup| NewAnswerMap {
             answer_id: *tup.0,
             answer_string: &tup.1,
+            submitted_by: Some(actor.key_id),
         })
         .collect::<Vec<_>>();
-    diesel::insert_into(answer_strings::table)
+    answer_map.sort_by_key(|a| a.answer_id);
+    let mut string_rows = diesel::insert_into(answer_strings::table)
         .values(&answer_map)
         .on_conflict(answer_strings::answer_id)
         .do_update()
-        .set(answer_strings::answer_string.eq(excluded(answer_strings::answer_string)))
+        .set((
+            answer_strings::answer_string.eq(excluded(answer_strings::answer_string)),
+            answer_strings::submitted_by.eq(excluded(answer_strings::submitted_by)),
+        ))
         .execute(conn)?;
-    let question_map = data
+    let mut question_map = data
         .question_map
         .iter()
         .map(|tup| NewQuestionMap {
             question_id: *tup.0,
             question_string: &tup.1,
+            submitted_by: Some(actor.key_id),
         })
         .collect::<Vec<_>>();
-    diesel::insert_into(question_strings::table)
+    question_map.sort_by_key(|q| q.question_id);
+    string_rows += diesel::insert_into(question_strings::table)
         .values(&question_map)
         .on_conflict(question_strings::question_id)
         .do_update()
-        .set(question_strings::question_string.eq(excluded(question_strings::question_string)))
+        .set((
+            question_strings::question_string.eq(excluded(question_strings::question_string)),
+            question_strings::submitted_by.eq(excluded(question_strings::submitted_by)),
+        ))
         .execute(conn)?;
     let group_text = &data.group_text[..];
-    let id = if let Ok(id) = diesel::insert_into(groups::table)
+    // Updating the conflicting row makes RETURNING yield the existing id.
+    let id = diesel::insert_into(groups::table)
         .values(groups::text.eq(group_text))
+        .on_conflict(groups::text)
+        .do_update()
+        .set(groups::text.eq(excluded(groups::text)))
         .returning(groups::id)
-        .get_result::<Uuid>(conn)
-    {
-        id
-    } else {
-        groups::table
-            .filter(groups::text.eq(group_text))
-            .select(groups::id)
-            .first::<Uuid>(conn)?
-    };
-    let answers = data
+        .get_result::<Uuid>(conn)?;
+    let mut answers = data
         .unknown_questions
         .into_iter()
         .map(|(qid, guess)| Answer {
@@ -93,20 +198,129 @@ pub fn upload_call(conn: &PgConnection, data: JsApiUpload) -> QueryResult<()> {
             question_id: qid,
             reviewed: false,
             test: id,
+            submitted_by: Some(actor.key_id),
         })
         .collect::<Vec<_>>();
-    diesel::insert_into(answers::table)
-        .values(&answers)
-        .on_conflict(answers::question_id)
+    answers.sort_by_key(|a| a.question_id);
+    let uploaded_ids = answers.iter().map(|a| a.question_id).collect::<Vec<_>>();
+    // Locking the existing rows keeps them from being reviewed before the
+    // upsert below.
+    let existing = answers::table
+        .filter(answers::question_id.eq(any(&uploaded_ids)))
+        .order(answers::question_id)
+        .for_update()
+        .load::<Answer>(conn)?;
+    let reviewed = existing
+        .iter()
+        .filter(|a| a.reviewed)
+        .map(|a| (a.question_id, a.answer_used))
+        .collect::<HashMap<_, _>>();
+    let now = Utc::now();
+    let mut proposals = Vec::new();
+    answers.retain(|answer| match reviewed.get(&answer.question_id) {
+        None => true,
+        Some(&used) if used == answer.answer_used => false,
+        Some(_) => {
+            proposals.push(NewAnswerProposal {
+                question_id: answer.question_id,
+                answer_used: answer.answer_used,
+                valid_answers: answer.valid_answers.clone(),
+                test: answer.test,
+                submitted_by: answer.submitted_by,
+                created_at: now,
+            });
+            false
+        }
+    });
+    let written = if answers.is_empty() {
+        Vec::new()
+    } else {
+        diesel::insert_into(answers::table)
+            .values(&answers)
+            .on_conflict(answers::question_id)
+            .do_update()
+            .set((
+                answers::answer_used.eq(excluded(answers::answer_used)),
+                answers::reviewed.eq(false),
+                answers::test.eq(id),
+                answers::valid_answers.eq(excluded(answers::valid_answers)),
+                answers::submitted_by.eq(excluded(answers::submitted_by)),
+            ))
+            .get_results::<Answer>(conn)?
+    };
+    let answer_rows = written.len();
+    write_revisions(
+        conn,
+        pair_changes(existing, written),
+        Some(actor.key_id),
+        "upload",
+    )?;
+    diesel::insert_into(answer_proposals::table)
+        .values(&proposals)
+        .on_conflict((answer_proposals::question_id, answer_proposals::answer_used))
         .do_update()
         .set((
-            answers::answer_used.eq(excluded(answers::answer_used)),
-            answers::reviewed.eq(false),
-            answers::test.eq(id),
-            answers::valid_answers.eq(excluded(answers::valid_answers)),
+            answer_proposals::valid_answers.eq(excluded(answer_proposals::valid_answers)),
+            answer_proposals::test.eq(excluded(answer_proposals::test)),
+            answer_proposals::submitted_by.eq(excluded(answer_proposals::submitted_by)),
+            answer_proposals::created_at.eq(excluded(answer_proposals::created_at)),
         ))
         .execute(conn)?;
-    Ok(())
+    let mut question_ids = answers
+        .iter()
+        .map(|a| a.question_id)
+        .chain(proposals.iter().map(|p| p.question_id))
+        .collect::<Vec<_>>();
+    question_ids.sort();
+    write_audit_entry(
+        conn,
+        actor,
+        "upload",
+        question_ids,
+        answer_rows,
+        string_rows,
+    )
+}
+
+/// Applies the proposal `id` to its reviewed answer and removes it along
+/// with the other proposals for the same question, which it supersedes.
+/// Returns `false` if there is no such proposal.
+pub fn accept_proposal(conn: &PgConnection, id: i64) -> QueryResult<bool> {
+    conn.transaction(|| {
+        let proposal = diesel::delete(answer_proposals::table.find(id))
+            .get_result::<AnswerProposal>(conn)
+            .optional()?;
+        let proposal = match proposal {
+            Some(proposal) => proposal,
+            None => return Ok(false),
+        };
+        let old = answers::table
+            .find(proposal.question_id)
+            .for_update()
+            .first::<Answer>(conn)?;
+        let new = diesel::update(answers::table.find(proposal.question_id))
+            .set((
+                answers::answer_used.eq(proposal.answer_used),
+                answers::valid_answers.eq(proposal.valid_answers),
+                answers::test.eq(proposal.test),
+                answers::submitted_by.eq(proposal.submitted_by),
+                answers::reviewed.eq(true),
+            ))
+            .get_result::<Answer>(conn)?;
+        diesel::delete(
+            answer_proposals::table.filter(answer_proposals::question_id.eq(proposal.question_id)),
+        )
+        .execute(conn)?;
+        write_revisions(conn, vec![(Some(old), Some(new))], None, "accept_proposal")?;
+        Ok(true)
+    })
+}
+
+/// Discards the proposal `id`. Returns `false` if there is no such
+/// proposal.
+pub fn reject_proposal(conn: &PgConnection, id: i64) -> QueryResult<bool> {
+    let deleted = diesel::delete(answer_proposals::table.find(id)).execute(conn)?;
+    Ok(deleted > 0)
 }
 
 pub fn get_data(conn: &PgConnection) -> QueryResult<HashMap<i32, i32>> {
@@ -118,24 +332,157 @@ pub fn get_data(conn: &PgConnection) -> QueryResult<HashMap<i32, i32>> {
         .collect())
 }
 
-pub fn set_reviewed(conn: &PgConnection, ids: &[i32]) -> QueryResult<()> {
-    diesel::update(answers::table.filter(answers::question_id.eq(any(ids))))
-        .set(answers::reviewed.eq(true))
-        .execute(conn)?;
-    Ok(())
+pub fn set_reviewed(conn: &PgConnection, ids: &[i32], actor: &Actor) -> QueryResult<()> {
+    conn.transaction(|| {
+        let old = answers::table
+            .filter(answers::question_id.eq(any(ids)))
+            .order(answers::question_id)
+            .for_update()
+            .load::<Answer>(conn)?;
+        let new = diesel::update(answers::table.filter(answers::question_id.eq(any(ids))))
+            .set(answers::reviewed.eq(true))
+            .get_results::<Answer>(conn)?;
+        let updated = new.iter().map(|a| a.question_id).collect();
+        let answer_rows = new.len();
+        write_revisions(
+            conn,
+            pair_changes(old, new),
+            Some(actor.key_id),
+            "set_reviewed",
+        )?;
+        write_audit_entry(conn, actor, "set_reviewed", updated, answer_rows, 0)
+    })
+}
+
+/// Marks the answer to `question_id` as reviewed, optionally replacing the
+/// answer used. Returns `false` if there is no such answer.
+pub fn review_answer(
+    conn: &PgConnection,
+    question_id: i32,
+    answer_used: Option<i32>,
+) -> QueryResult<bool> {
+    conn.transaction(|| {
+        let old = answers::table
+            .find(question_id)
+            .for_update()
+            .first::<Answer>(conn)
+            .optional()?;
+        let old = match old {
+            Some(old) => old,
+            None => return Ok(false),
+        };
+        let new = diesel::update(answers::table.find(question_id))
+            .set((
+                answers::answer_used.eq(answer_used.unwrap_or(old.answer_used)),
+                answers::reviewed.eq(true),
+            ))
+            .get_result::<Answer>(conn)?;
+        write_revisions(conn, vec![(Some(old), Some(new))], None, "review")?;
+        Ok(true)
+    })
+}
+
+/// Deletes the answer to `question_id`. Returns `false` if there is no
+/// such answer.
+pub fn delete_answer(conn: &PgConnection, question_id: i32) -> QueryResult<bool> {
+    conn.transaction(|| {
+        let old = diesel::delete(answers::table.find(question_id))
+            .get_result::<Answer>(conn)
+            .optional()?;
+        let deleted = old.is_some();
+        write_revisions(conn, vec![(old, None)], None, "delete")?;
+        Ok(deleted)
+    })
 }
 
+/// Restores the answer changed by the revision `id` to its state before
+/// that change, recording the revert as a new revision. Returns `false` if
+/// there is no such revision.
+///
+/// A recreated answer has no submitter.
+pub fn revert_revision(conn: &PgConnection, id: i64) -> QueryResult<bool> {
+    conn.transaction(|| {
+        let revision = answer_revisions::table
+            .find(id)
+            .first::<AnswerRevision>(conn)
+            .optional()?;
+        let revision = match revision {
+            Some(revision) => revision,
+            None => return Ok(false),
+        };
+        let question_id = revision.question_id;
+        let current = answers::table
+            .find(question_id)
+            .for_update()
+            .first::<Answer>(conn)
+            .optional()?;
+        let restored = match (
+            revision.old_answer_used,
+            revision.old_reviewed,
+            revision.old_valid_answers,
+            revision.old_test,
+        ) {
+            (Some(answer_used), Some(reviewed), Some(valid_answers), Some(test)) => {
+                let answer = Answer {
+                    question_id,
+                    answer_used,
+                    reviewed,
+                    test,
+                    valid_answers,
+                    submitted_by: revision.old_submitted_by,
+                };
+                let restored = diesel::insert_into(answers::table)
+                    .values(&answer)
+                    .on_conflict(answers::question_id)
+                    .do_update()
+                    .set((
+                        answers::answer_used.eq(excluded(answers::answer_used)),
+                        answers::reviewed.eq(excluded(answers::reviewed)),
+                        answers::test.eq(excluded(answers::test)),
+                        answers::valid_answers.eq(excluded(answers::valid_answers)),
+                        answers::submitted_by.eq(excluded(answers::submitted_by)),
+                    ))
+                    .get_result::<Answer>(conn)?;
+                Some(restored)
+            }
+            _ => {
+                diesel::delete(answers::table.find(question_id)).execute(conn)?;
+                None
+            }
+        };
+        write_revisions(conn, vec![(current, restored)], None, "revert")?;
+        Ok(true)
+    })
+}
+
+/// Keyed MAC of `key` with the server-side `pepper`.
+fn api_key_mac(pepper: &str, key: &str) -> Blake2b {
+    // Blake2b keys are at most 64 bytes, so longer peppers are hashed.
+    let mut mac = Blake2b::new_varkey(&Blake2b::digest(pepper.as_bytes()))
+        .expect("digest is a valid key length");
+    Mac::input(&mut mac, key.as_bytes());
+    mac
+}
+
+/// Generates an API key of the form `prefix.secret` and stores its MAC.
 pub fn gen_api_key(
     conn: &PgConnection,
     note: Option<&str>,
-    write_access: bool,
+    scopes: &[Scope],
+    expires_at: Option<DateTime<Utc>>,
+    daily_quota: Option<i32>,
+    pepper: &str,
 ) -> QueryResult<String> {
-    let key = generate_api_key(128);
-    let hash = Blake2b::digest(key.as_bytes());
+    let prefix = generate_api_key(KEY_PREFIX_LEN);
+    let key = format!("{}.{}", prefix, generate_api_key(KEY_SECRET_LEN));
+    let hash = Mac::result(api_key_mac(pepper, &key)).code();
     let new = NewApiKey {
         hash: &hash[..],
         note,
-        write_access,
+        scopes: scopes.iter().map(|s| s.as_str().to_owned()).collect(),
+        prefix: Some(&prefix),
+        expires_at,
+        daily_quota,
     };
     diesel::insert_into(api_keys::table)
         .values(&new)
@@ -143,27 +490,273 @@ pub fn gen_api_key(
     Ok(key)
 }
 
+/// Looks up the API key `key`, ignoring expired and revoked keys. Scopes
+/// are checked by the caller.
+///
+/// `prefix.secret` keys are found by prefix and verified against their
+/// MAC, which needs `pepper`. Legacy keys without a prefix are matched on
+/// their plain digest until they are rotated.
 pub fn check_api_key(
     conn: &PgConnection,
     key: &str,
-) -> QueryResult<Option<(Uuid, Option<String>)>> {
-    let hash = Blake2b::digest(key.as_bytes());
-    Ok(api_keys::table
-        .filter(api_keys::write_access)
-        .filter(api_keys::hash.eq(&hash[..]))
-        .select((api_keys::id, api_keys::note))
+    pepper: Option<&str>,
+) -> QueryResult<Option<ApiKey>> {
+    let active = api_keys::table
+        .filter(api_keys::revoked_at.is_null())
+        .filter(
+            api_keys::expires_at
+                .is_null()
+                .or(api_keys::expires_at.gt(Utc::now())),
+        )
+        .into_boxed();
+    let prefix = match key.find('.') {
+        Some(idx) => &key[..idx],
+        None => {
+            let hash = Blake2b::digest(key.as_bytes());
+            return active
+                .filter(api_keys::prefix.is_null())
+                .filter(api_keys::hash.eq(&hash[..]))
+                .first(conn)
+                .optional();
+        }
+    };
+    let pepper = match pepper {
+        Some(pepper) => pepper,
+        None => return Ok(None),
+    };
+    let found = active
+        .filter(api_keys::prefix.eq(prefix))
+        .first::<ApiKey>(conn)
+        .optional()?;
+    Ok(found.filter(|found| api_key_mac(pepper, key).verify(&found.hash).is_ok()))
+}
+
+/// Returns the number of requests recorded for the key `id` in the
+/// current UTC day.
+pub fn api_key_usage_today(conn: &PgConnection, id: Uuid) -> QueryResult<i32> {
+    let requests = api_key_usage::table
+        .find((id, Utc::now().naive_utc().date()))
+        .select(api_key_usage::requests)
         .first(conn)
-        .optional()?)
+        .optional()?;
+    Ok(requests.unwrap_or(0))
+}
+
+/// Records batched requests made with API keys. Usage of keys removed in
+/// the meantime is dropped.
+pub fn record_api_key_uses(conn: &PgConnection, uses: &[ApiKeyUse]) -> QueryResult<()> {
+    conn.transaction(|| {
+        for usage in uses {
+            let updated = diesel::update(api_keys::table.find(usage.key_id))
+                .set((
+                    api_keys::last_used_at.eq(usage.last_used_at),
+                    api_keys::use_count.eq(api_keys::use_count + i64::from(usage.requests)),
+                ))
+                .execute(conn)?;
+            if updated == 0 {
+                continue;
+            }
+            diesel::insert_into(api_key_usage::table)
+                .values((
+                    api_key_usage::key_id.eq(usage.key_id),
+                    api_key_usage::day.eq(usage.day),
+                    api_key_usage::requests.eq(usage.requests),
+                ))
+                .on_conflict((api_key_usage::key_id, api_key_usage::day))
+                .do_update()
+                .set(
+                    api_key_usage::requests
+                        .eq(api_key_usage::requests + excluded(api_key_usage::requests)),
+                )
+                .execute(conn)?;
+        }
+        Ok(())
+    })
 }
 
-pub fn check_api_key_r(
+/// Sets the daily request quota of the key `id`, `None` for unlimited.
+pub fn set_api_key_quota(conn: &PgConnection, id: Uuid, quota: Option<i32>) -> QueryResult<bool> {
+    let updated = diesel::update(api_keys::table.find(id))
+        .set(api_keys::daily_quota.eq(quota))
+        .execute(conn)?;
+    Ok(updated > 0)
+}
+
+/// Issues a replacement for the key `id` with the same scopes, note, quota
+/// and expiry.
+///
+/// The old key stays valid for `grace` unless it expires earlier. Returns
+/// `None` if there is no active key `id`.
+pub fn rotate_api_key(
     conn: &PgConnection,
-    key: &str,
-) -> QueryResult<Option<(Uuid, Option<String>)>> {
-    let hash = Blake2b::digest(key.as_bytes());
-    Ok(api_keys::table
-        .filter(api_keys::hash.eq(&hash[..]))
-        .select((api_keys::id, api_keys::note))
-        .first(conn)
-        .optional()?)
+    id: Uuid,
+    grace: Duration,
+    pepper: &str,
+) -> QueryResult<Option<String>> {
+    conn.transaction(|| {
+        let old = api_keys::table
+            .find(id)
+            .filter(api_keys::revoked_at.is_null())
+            .for_update()
+            .first::<ApiKey>(conn)
+            .optional()?;
+        let old = match old {
+            Some(old) => old,
+            None => return Ok(None),
+        };
+        let grace_end = Utc::now() + grace;
+        let expires_at = match old.expires_at {
+            Some(expires_at) if expires_at < grace_end => expires_at,
+            _ => grace_end,
+        };
+        diesel::update(api_keys::table.find(id))
+            .set(api_keys::expires_at.eq(expires_at))
+            .execute(conn)?;
+        let scopes = old
+            .scopes
+            .iter()
+            .filter_map(|s| s.parse().ok())
+            .collect::<Vec<Scope>>();
+        let key = gen_api_key(
+            conn,
+            old.note.as_deref(),
+            &scopes,
+            old.expires_at,
+            old.daily_quota,
+            pepper,
+        )?;
+        Ok(Some(key))
+    })
+}
+
+/// Revokes the key `id` immediately.
+pub fn revoke_api_key(conn: &PgConnection, id: Uuid) -> QueryResult<bool> {
+    let updated = diesel::update(
+        api_keys::table
+            .find(id)
+            .filter(api_keys::revoked_at.is_null()),
+    )
+    .set(api_keys::revoked_at.eq(Utc::now()))
+    .execute(conn)?;
+    Ok(updated > 0)
+}
+
+/// Rows removed by [`purge_api_key_rows`].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PurgedRows {
+    /// Answers the key created, which were deleted
+    pub answers: usize,
+    /// Answers restored to their state before the key changed them
+    pub restored_answers: usize,
+    /// Pending proposals the key submitted
+    pub proposals: usize,
+    pub answer_strings: usize,
+    pub question_strings: usize,
+}
+
+/// Returns the state of `question_id` before the latest run of changes made
+/// with the key `id`, or `None` if the key created the answer or the
+/// history does not show the key changing it.
+fn state_before_key(
+    conn: &PgConnection,
+    question_id: i32,
+    id: Uuid,
+) -> QueryResult<Option<Answer>> {
+    let history = answer_revisions::table
+        .filter(answer_revisions::question_id.eq(question_id))
+        .order(answer_revisions::id.desc())
+        .load::<AnswerRevision>(conn)?;
+    let first = history
+        .into_iter()
+        .take_while(|rev| rev.key_id == Some(id))
+        .last();
+    Ok(first.and_then(|rev| {
+        Some(Answer {
+            question_id,
+            answer_used: rev.old_answer_used?,
+            reviewed: rev.old_reviewed?,
+            test: rev.old_test?,
+            valid_answers: rev.old_valid_answers?,
+            submitted_by: rev.old_submitted_by,
+        })
+    }))
+}
+
+/// Undoes the unreviewed answers last uploaded with the key `id` and
+/// deletes its pending proposals, then deletes the strings it uploaded that
+/// no remaining answer or proposal refers to.
+///
+/// Answers are restored to their state before the key changed them, as
+/// recorded in the revision history, and deleted if the key created them.
+/// Reviewed answers are kept. Strings still in use keep the text the key
+/// uploaded since string history is not recorded.
+pub fn purge_api_key_rows(conn: &PgConnection, id: Uuid) -> QueryResult<PurgedRows> {
+    conn.transaction(|| {
+        let current = answers::table
+            .filter(answers::submitted_by.eq(id))
+            .filter(answers::reviewed.eq(false))
+            .order(answers::question_id)
+            .for_update()
+            .load::<Answer>(conn)?;
+        let mut purged = PurgedRows::default();
+        let mut changes = Vec::new();
+        for old in current {
+            let question_id = old.question_id;
+            let new = match state_before_key(conn, question_id, id)? {
+                Some(prior) => {
+                    purged.restored_answers += 1;
+                    let restored = diesel::update(answers::table.find(question_id))
+                        .set((
+                            answers::answer_used.eq(prior.answer_used),
+                            answers::reviewed.eq(prior.reviewed),
+                            answers::test.eq(prior.test),
+                            answers::valid_answers.eq(prior.valid_answers),
+                            answers::submitted_by.eq(prior.submitted_by),
+                        ))
+                        .get_result::<Answer>(conn)?;
+                    Some(restored)
+                }
+                None => {
+                    purged.answers += 1;
+                    diesel::delete(answers::table.find(question_id)).execute(conn)?;
+                    None
+                }
+            };
+            changes.push((Some(old), new));
+        }
+        write_revisions(conn, changes, None, "purge_key")?;
+        purged.proposals =
+            diesel::delete(answer_proposals::table.filter(answer_proposals::submitted_by.eq(id)))
+                .execute(conn)?;
+        let answer_strings = diesel::delete(
+            answer_strings::table
+                .filter(answer_strings::submitted_by.eq(id))
+                .filter(not(exists(
+                    answers::table.filter(
+                        answers::answer_used
+                            .eq(answer_strings::answer_id)
+                            .or(answer_strings::answer_id.eq(any(answers::valid_answers))),
+                    ),
+                )))
+                .filter(not(exists(
+                    answer_proposals::table.filter(
+                        answer_proposals::answer_used
+                            .eq(answer_strings::answer_id)
+                            .or(answer_strings::answer_id.eq(any(answer_proposals::valid_answers))),
+                    ),
+                ))),
+        )
+        .execute(conn)?;
+        let question_strings = diesel::delete(
+            question_strings::table
+                .filter(question_strings::submitted_by.eq(id))
+                .filter(not(exists(answers::table.filter(
+                    answers::question_id.eq(question_strings::question_id),
+                )))),
+        )
+        .execute(conn)?;
+        purged.answer_strings = answer_strings;
+        purged.question_strings = question_strings;
+        Ok(purged)
+    })
 }
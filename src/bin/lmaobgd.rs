@@ -1,60 +1,326 @@
 use actix_cors::Cors;
+use actix_web::dev::{Payload, Service, ServiceRequest, ServiceResponse, Transform};
 use actix_web::http::header;
-use actix_web::{get, middleware, post, web, App, FromRequest, HttpResponse, HttpServer};
-use actix_web_httpauth::extractors::basic::BasicAuth;
+use actix_web::http::header::Header;
+use actix_web::{
+    get, middleware, web, App, FromRequest, HttpMessage, HttpRequest, HttpResponse, HttpServer,
+};
+use actix_web_httpauth::headers::authorization::{Authorization, Basic, Bearer};
+use chrono::{Duration, NaiveDate, Utc};
 use diesel::pg::PgConnection;
 use diesel::r2d2::ConnectionManager;
+use futures::future::{err, ok, LocalBoxFuture, Ready};
+use lmaobgd::models::Scope;
+use lmaobgd::ratelimit::{
+    client_ip, too_many_requests, IpRateLimit, ProxyHeader, Rate, RateLimiter,
+};
 use lmaobgd::{actions, models};
-use log::info;
+use log::{info, warn};
+use std::cell::RefCell;
 use std::collections::HashMap;
 use std::net::SocketAddr;
-use std::sync::{Arc, Mutex};
+use std::rc::Rc;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::task::{Context, Poll};
 use structopt::StructOpt;
+use uuid::Uuid;
 
 type DbPool = r2d2::Pool<ConnectionManager<PgConnection>>;
 #[derive(Clone)]
 struct RoDbPool(DbPool);
+/// Server-side secret for API key MACs.
+struct ApiKeyPepper(Option<String>);
+/// The proxy header client addresses are taken from, if any.
+struct TrustProxy(Option<ProxyHeader>);
+
+/// Scope rate limits used unless overridden with `--scope-rate`.
+const DEFAULT_SCOPE_RATES: &[(Scope, &str)] = &[
+    (Scope::Read, "600/m"),
+    (Scope::Upload, "30/m"),
+    (Scope::Review, "120/m"),
+];
+
+/// Token buckets per API key and scope.
+struct KeyLimits {
+    limiter: RateLimiter<(Uuid, Scope)>,
+    rates: HashMap<Scope, Rate>,
+}
+
+/// How often buffered API key usage is written to the database.
+const USAGE_FLUSH_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// API key usage not yet written to the database.
+#[derive(Default)]
+struct KeyUsage(Mutex<HashMap<(Uuid, NaiveDate), models::ApiKeyUse>>);
+
+impl KeyUsage {
+    /// Returns the buffered requests made with the key `id` on `day`.
+    fn pending(&self, id: Uuid, day: NaiveDate) -> i32 {
+        self.0
+            .lock()
+            .unwrap()
+            .get(&(id, day))
+            .map_or(0, |usage| usage.requests)
+    }
+
+    /// Buffers a request made with the key `id`.
+    fn record(&self, id: Uuid) {
+        let now = Utc::now();
+        self.add(models::ApiKeyUse {
+            key_id: id,
+            day: now.naive_utc().date(),
+            requests: 1,
+            last_used_at: now,
+        });
+    }
+
+    fn add(&self, usage: models::ApiKeyUse) {
+        let mut pending = self.0.lock().unwrap();
+        let entry = pending
+            .entry((usage.key_id, usage.day))
+            .or_insert(models::ApiKeyUse {
+                requests: 0,
+                ..usage.clone()
+            });
+        entry.requests += usage.requests;
+        entry.last_used_at = entry.last_used_at.max(usage.last_used_at);
+    }
+
+    /// Writes the buffered usage, keeping it for the next flush on failure.
+    async fn flush(&self, pool: &DbPool) {
+        let mut uses = self
+            .0
+            .lock()
+            .unwrap()
+            .drain()
+            .map(|(_, usage)| usage)
+            .collect::<Vec<_>>();
+        if uses.is_empty() {
+            return;
+        }
+        uses.sort_by_key(|usage| usage.last_used_at);
+        let pool = pool.clone();
+        let written = uses.clone();
+        let result = web::block(move || -> Result<_, failure::Error> {
+            let db = pool.get()?;
+            actions::record_api_key_uses(&db, &written)?;
+            Ok(())
+        })
+        .await;
+        if let Err(e) = result {
+            warn!("unable to record api key usage: {}", e);
+            for usage in uses {
+                self.add(usage);
+            }
+        }
+    }
+}
+
+/// A `scope=rate` command line argument.
+struct ScopeRate(Scope, Rate);
+
+impl FromStr for ScopeRate {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(2, '=');
+        let scope = parts.next().unwrap_or_default().parse()?;
+        let rate = parts
+            .next()
+            .ok_or_else(|| failure::format_err!("expected scope=rate, e.g. upload=30/m"))?
+            .parse()?;
+        Ok(ScopeRate(scope, rate))
+    }
+}
+
+/// Header carrying the API key as an alternative to `Authorization`.
+const API_KEY_HEADER: &str = "x-api-key";
+
+/// Returns the API key from the `X-Api-Key` header, a bearer token or the
+/// Basic auth user name, in that order.
+fn presented_key<T: HttpMessage>(req: &T) -> Option<String> {
+    if let Some(key) = req.headers().get(API_KEY_HEADER) {
+        return key.to_str().ok().map(|key| key.trim().to_owned());
+    }
+    if let Ok(auth) = Authorization::<Bearer>::parse(req) {
+        return Some(auth.into_scheme().token().to_string());
+    }
+    Authorization::<Basic>::parse(req)
+        .ok()
+        .map(|auth| auth.into_scheme().user_id().to_string())
+}
+
+/// An API key that passed [`KeyAuth`].
+struct AuthenticatedKey {
+    key: models::ApiKey,
+    client_ip: Option<String>,
+}
+
+impl AuthenticatedKey {
+    /// The key and client address to record in the audit log.
+    fn actor(&self) -> models::Actor {
+        models::Actor {
+            key_id: self.key.id,
+            client_ip: self.client_ip.clone(),
+        }
+    }
+}
+
+impl FromRequest for AuthenticatedKey {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let key = req.extensions_mut().remove::<AuthenticatedKey>();
+        match key {
+            Some(key) => ok(key),
+            None => err(HttpResponse::InternalServerError().finish().into()),
+        }
+    }
+}
+
+/// Middleware authenticating the API key of a request before the handler
+/// reads the body.
+///
+/// Keys without the scope are rejected with `403 Forbidden`, then the
+/// scope rate limit and daily quota are checked, so rejected requests do
+/// not count towards the quota.
+#[derive(Clone, Copy)]
+struct KeyAuth(Scope);
+
+impl<S> Transform<S> for KeyAuth
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse, Error = actix_web::Error>
+        + 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse;
+    type Error = actix_web::Error;
+    type InitError = ();
+    type Transform = KeyAuthMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(KeyAuthMiddleware {
+            service: Rc::new(RefCell::new(service)),
+            scope: self.0,
+        })
+    }
+}
+
+struct KeyAuthMiddleware<S> {
+    service: Rc<RefCell<S>>,
+    scope: Scope,
+}
+
+impl<S> Service for KeyAuthMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse, Error = actix_web::Error>
+        + 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.service.borrow_mut().poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let scope = self.scope;
+        Box::pin(async move {
+            match authenticate(&req, scope).await {
+                Ok(key) => {
+                    req.extensions_mut().insert(key);
+                    let fut = service.borrow_mut().call(req);
+                    fut.await
+                }
+                Err(e) => Ok(req.error_response(e)),
+            }
+        })
+    }
+}
+
+/// Looks up the key presented with `req` and checks it against `scope`,
+/// its rate limit and its daily quota.
+async fn authenticate(
+    req: &ServiceRequest,
+    scope: Scope,
+) -> Result<AuthenticatedKey, actix_web::Error> {
+    let key = presented_key(req).ok_or_else(|| {
+        HttpResponse::Unauthorized()
+            .header(header::WWW_AUTHENTICATE, "Bearer")
+            .finish()
+    })?;
+    let pool = req.app_data::<RoDbPool>();
+    let pepper = req.app_data::<ApiKeyPepper>();
+    let limits = req.app_data::<KeyLimits>();
+    let usage = req.app_data::<KeyUsage>();
+    let (pool, pepper, limits, usage) = match (pool, pepper, limits, usage) {
+        (Some(pool), Some(pepper), Some(limits), Some(usage)) => (pool, pepper, limits, usage),
+        _ => return Err(HttpResponse::InternalServerError().finish().into()),
+    };
+    let proxy_header = req.app_data::<TrustProxy>().and_then(|t| t.0);
+    let client_ip =
+        client_ip(req.headers(), req.peer_addr(), proxy_header).map(|ip| ip.to_string());
+    let key = web::block(move || -> Result<_, failure::Error> {
+        let db = pool.0.get()?;
+        let key = match actions::check_api_key(&db, &key, pepper.0.as_deref())? {
+            Some(key) => key,
+            None => return Ok(None),
+        };
+        let used_today = actions::api_key_usage_today(&db, key.id)?;
+        Ok(Some((key, used_today)))
+    })
+    .await?
+    .ok_or_else(|| HttpResponse::Unauthorized().finish())?;
+    let (key, used_today) = key;
+    if !key.has_scope(scope) {
+        return Err(HttpResponse::Forbidden().finish().into());
+    }
+    if let Some(rate) = limits.rates.get(&scope) {
+        if let Err(wait) = limits.limiter.check((key.id, scope), rate) {
+            return Err(too_many_requests(wait).into());
+        }
+    }
+    let now = Utc::now();
+    let used_today = used_today + usage.pending(key.id, now.naive_utc().date());
+    if let Some(quota) = key.daily_quota {
+        if used_today >= quota {
+            let midnight = (now.date() + Duration::days(1)).and_hms(0, 0, 0);
+            let wait = (midnight - now).to_std().unwrap_or_default();
+            return Err(too_many_requests(wait).into());
+        }
+    }
+    usage.record(key.id);
+    info!(
+        "api access id={} note={}",
+        key.id,
+        key.note.as_deref().unwrap_or("")
+    );
+    Ok(AuthenticatedKey { key, client_ip })
+}
 
-#[post("/upload")]
 async fn api_upload(
     pool: web::Data<DbPool>,
-    auth: BasicAuth,
+    key: AuthenticatedKey,
     web::Json(json): web::Json<models::JsApiUpload>,
 ) -> Result<HttpResponse, actix_web::Error> {
     let db = web::block(move || pool.get()).await?;
-    let db = Arc::new(Mutex::new(db));
-    let key = auth.user_id().clone();
-    let db2 = Arc::clone(&db);
-    let (id, note) = web::block(move || actions::check_api_key(&db2.lock().unwrap(), &key))
-        .await?
-        .ok_or_else(|| HttpResponse::Unauthorized())?;
-    info!(
-        "api access id={} note={}",
-        id,
-        note.as_deref().unwrap_or("")
-    );
-    web::block(move || actions::upload_call(&db.lock().unwrap(), json)).await?;
+    let actor = key.actor();
+    web::block(move || actions::upload_call(&db, json, &actor)).await?;
     Ok(HttpResponse::Ok().finish())
 }
 
-#[get("/data")]
 async fn api_data(
     pool: web::Data<RoDbPool>,
-    auth: BasicAuth,
 ) -> Result<web::Json<HashMap<i32, i32>>, actix_web::Error> {
     let db = web::block(move || pool.0.get()).await?;
-    let db = Arc::new(Mutex::new(db));
-    let db2 = Arc::clone(&db);
-    let key = auth.user_id().clone();
-    let (id, note) = web::block(move || actions::check_api_key_r(&db2.lock().unwrap(), &key))
-        .await?
-        .ok_or_else(|| HttpResponse::Unauthorized())?;
-    info!(
-        "api access id={} note={}",
-        id,
-        note.as_deref().unwrap_or("")
-    );
-    let data = web::block(move || actions::get_data(&db.lock().unwrap())).await?;
+    let data = web::block(move || actions::get_data(&db)).await?;
     Ok(web::Json(data))
 }
 
@@ -63,40 +329,45 @@ async fn api_check() -> HttpResponse {
     HttpResponse::Ok().finish()
 }
 
-#[post("/set_reviewed")]
 async fn api_set_reviewed(
     pool: web::Data<DbPool>,
+    key: AuthenticatedKey,
     web::Json(ids): web::Json<Vec<i32>>,
-    auth: BasicAuth,
 ) -> Result<HttpResponse, actix_web::Error> {
     let db = web::block(move || pool.get()).await?;
-    let db = Arc::new(Mutex::new(db));
-    let key = auth.user_id().clone();
-    let db2 = Arc::clone(&db);
-    let (id, note) = web::block(move || actions::check_api_key(&db2.lock().unwrap(), &key))
-        .await?
-        .ok_or_else(|| HttpResponse::Unauthorized())?;
-    info!(
-        "api access id={} note={}",
-        id,
-        note.as_deref().unwrap_or("")
-    );
-    web::block(move || actions::set_reviewed(&db.lock().unwrap(), &ids)).await?;
+    let actor = key.actor();
+    web::block(move || actions::set_reviewed(&db, &ids, &actor)).await?;
     Ok(HttpResponse::Ok().finish())
 }
 
 fn api() -> actix_web::Scope {
     web::scope("/api")
-        .service(api_data)
-        .service(api_upload)
-        .service(api_set_reviewed)
+        .service(
+            web::resource("/data")
+                .wrap(KeyAuth(Scope::Read))
+                .route(web::get().to(api_data)),
+        )
+        .service(
+            web::resource("/upload")
+                .wrap(KeyAuth(Scope::Upload))
+                .route(web::post().to(api_upload)),
+        )
+        .service(
+            web::resource("/set_reviewed")
+                .wrap(KeyAuth(Scope::Review))
+                .route(web::post().to(api_set_reviewed)),
+        )
         .service(api_check)
 }
 
 fn cors() -> actix_cors::CorsFactory {
     Cors::new()
         .allowed_methods(vec!["GET", "POST"])
-        .allowed_headers(vec![header::CONTENT_TYPE, header::AUTHORIZATION])
+        .allowed_headers(vec![
+            header::CONTENT_TYPE,
+            header::AUTHORIZATION,
+            header::HeaderName::from_static(API_KEY_HEADER),
+        ])
         .finish()
 }
 
@@ -118,6 +389,20 @@ struct Args {
     /// Read-only database connection pool size
     #[structopt(long, default_value = "10")]
     db_read_only_pool_size: u32,
+    /// Secret mixed into API key hashes, only legacy keys are accepted
+    /// without it
+    #[structopt(long, env, hide_env_values = true)]
+    api_key_pepper: Option<String>,
+    /// Requests allowed per client IP, e.g. 600/m
+    #[structopt(long, default_value = "600/m")]
+    ip_rate: Rate,
+    /// Requests allowed per API key for a scope, e.g. upload=30/m
+    #[structopt(long, number_of_values = 1)]
+    scope_rate: Vec<ScopeRate>,
+    /// Take client IPs from the last entry of this header, as added by a
+    /// reverse proxy: forwarded or x-forwarded-for
+    #[structopt(long)]
+    trust_proxy_header: Option<ProxyHeader>,
 }
 
 #[actix_rt::main]
@@ -125,6 +410,22 @@ async fn main() -> Result<(), exitfailure::ExitFailure> {
     env_logger::init();
     let _ = dotenv::dotenv();
     let args = Args::from_args();
+    if args.api_key_pepper.is_none() {
+        warn!("API_KEY_PEPPER is not set, only legacy api keys will be accepted");
+    }
+    let pepper = web::Data::new(ApiKeyPepper(args.api_key_pepper));
+    let mut rates = DEFAULT_SCOPE_RATES
+        .iter()
+        .map(|(scope, rate)| Ok((*scope, rate.parse()?)))
+        .collect::<Result<HashMap<_, _>, failure::Error>>()?;
+    rates.extend(args.scope_rate.into_iter().map(|ScopeRate(s, r)| (s, r)));
+    let limits = web::Data::new(KeyLimits {
+        limiter: RateLimiter::new(),
+        rates,
+    });
+    let ip_limit = IpRateLimit::new(args.ip_rate, args.trust_proxy_header);
+    let trust_proxy = web::Data::new(TrustProxy(args.trust_proxy_header));
+    let usage = web::Data::new(KeyUsage::default());
 
     let cm = ConnectionManager::new(&args.database_url);
     let pool = DbPool::builder()
@@ -139,19 +440,37 @@ async fn main() -> Result<(), exitfailure::ExitFailure> {
         pool.clone()
     };
 
+    let flush_usage = usage.clone();
+    let flush_pool = pool.clone();
+    actix_rt::spawn(async move {
+        let mut interval = actix_rt::time::interval(USAGE_FLUSH_INTERVAL);
+        loop {
+            interval.tick().await;
+            flush_usage.flush(&flush_pool).await;
+        }
+    });
+
+    let server_pool = pool.clone();
+    let server_usage = usage.clone();
     HttpServer::new(move || {
         App::new()
-            .data(pool.clone())
+            .data(server_pool.clone())
             .data(RoDbPool(pool_ro.clone()))
+            .app_data(pepper.clone())
+            .app_data(limits.clone())
+            .app_data(trust_proxy.clone())
+            .app_data(server_usage.clone())
             .app_data(web::Json::<models::JsApiUpload>::configure(|cfg| {
                 cfg.limit(128 * 1024 * 1024)
             }))
             .service(api())
+            .wrap(ip_limit.clone())
             .wrap(cors())
             .wrap(middleware::Logger::default())
     })
     .bind(&args.bind)?
     .run()
     .await?;
+    usage.flush(&pool).await;
     Ok(())
 }
@@ -1,3 +1,4 @@
+use chrono::{DateTime, Duration, Utc};
 use diesel::dsl::{exists, not};
 use diesel::pg::expression::dsl::any;
 use diesel::pg::PgConnection;
@@ -5,11 +6,15 @@ use diesel::prelude::*;
 use dotenv::dotenv;
 use exitfailure::ExitFailure;
 use failure::ResultExt;
-use lmaobgd::actions::gen_api_key;
+use lmaobgd::actions::{
+    accept_proposal, delete_answer, gen_api_key, purge_api_key_rows, reject_proposal,
+    revert_revision, review_answer, revoke_api_key, rotate_api_key, set_api_key_quota,
+};
 use lmaobgd::display::*;
 use lmaobgd::models::*;
 use lmaobgd::schema::*;
 use std::collections::HashMap;
+use std::fmt::Debug;
 use std::fs::File;
 use std::io::{stdin, Write};
 use structopt::StructOpt;
@@ -30,12 +35,21 @@ enum Command {
     Dump,
     /// Generate a new api key
     ApiKey {
-        /// Allow write access
+        /// Scope to grant (read, upload, review or admin), defaults to read
+        #[structopt(short, long = "scope", number_of_values = 1)]
+        scopes: Vec<Scope>,
+        /// Grant the upload and review scopes in addition to read
         #[structopt(short, long)]
         write: bool,
         /// Note for key
         #[structopt(short, long)]
         note: Option<String>,
+        /// Number of days until the key expires
+        #[structopt(short, long)]
+        expires_in_days: Option<i64>,
+        /// Requests allowed per UTC day
+        #[structopt(short, long)]
+        quota: Option<i32>,
     },
     /// List api keys by id and note
     LsApi,
@@ -44,6 +58,78 @@ enum Command {
         /// API key id to remove
         id: Uuid,
     },
+    /// Issue a replacement for an api key, keeping the old one valid for a
+    /// grace period
+    RotateApi {
+        /// API key id to rotate
+        id: Uuid,
+        /// Hours the old key stays valid
+        #[structopt(short, long, default_value = "24")]
+        grace_hours: i64,
+    },
+    /// Revoke an api key immediately
+    RevokeApi {
+        /// API key id to revoke
+        id: Uuid,
+    },
+    /// Set the daily request quota of an api key
+    SetQuota {
+        /// API key id
+        id: Uuid,
+        /// Requests allowed per UTC day, unlimited if omitted
+        quota: Option<i32>,
+    },
+    /// Undo the unreviewed answers and delete the proposals and unused
+    /// strings uploaded with an api key
+    PurgeKey {
+        /// API key id
+        id: Uuid,
+    },
+    /// List uploads that disagree with reviewed answers
+    Proposals {
+        /// Only show proposals for this question id
+        #[structopt(short, long)]
+        question: Option<i32>,
+    },
+    /// Replace a reviewed answer with a proposal, discarding the other
+    /// proposals for the same question
+    AcceptProposal {
+        /// Proposal id
+        id: i64,
+    },
+    /// Discard a proposal
+    RejectProposal {
+        /// Proposal id
+        id: i64,
+    },
+    /// Show the changes made to a question's answer
+    History {
+        /// Id of the question
+        question_id: i32,
+    },
+    /// Restore an answer to its state before a revision
+    Revert {
+        /// Revision id, as shown by history
+        id: i64,
+    },
+    /// Show api writes from the audit log, newest first
+    Audit {
+        /// Only show writes made with this api key id
+        #[structopt(short, long)]
+        key: Option<Uuid>,
+        /// Only show writes at or after this RFC 3339 time
+        #[structopt(long)]
+        since: Option<DateTime<Utc>>,
+        /// Only show writes before this RFC 3339 time
+        #[structopt(long)]
+        until: Option<DateTime<Utc>>,
+        /// Only show writes touching this question id
+        #[structopt(short, long)]
+        question: Option<i32>,
+        /// Maximum number of entries to show
+        #[structopt(short, long, default_value = "100")]
+        limit: i64,
+    },
 }
 
 /// Command line interface for LmaoBGD administration.
@@ -52,21 +138,71 @@ struct Args {
     /// URL for database connection
     #[structopt(short, long, env = "DATABASE_URL", hide_env_values = true)]
     database_url: String,
+    /// Secret mixed into API key hashes, must match the server's
+    #[structopt(long, env = "API_KEY_PEPPER", hide_env_values = true)]
+    api_key_pepper: Option<String>,
     /// Command to execute
     #[structopt(subcommand)]
     command: Command,
 }
 
-fn new_api_key(db: PgConnection, write: bool, note: Option<String>) -> Result<(), failure::Error> {
-    let key = gen_api_key(&db, note.as_deref(), write)?;
+/// Returns the pepper needed to create keys, or an error if it is not set.
+fn require_pepper(pepper: Option<String>) -> Result<String, failure::Error> {
+    pepper.ok_or_else(|| failure::format_err!("API_KEY_PEPPER is required to create keys"))
+}
+
+fn new_api_key(
+    db: PgConnection,
+    pepper: Option<String>,
+    mut scopes: Vec<Scope>,
+    write: bool,
+    note: Option<String>,
+    expires_in_days: Option<i64>,
+    quota: Option<i32>,
+) -> Result<(), failure::Error> {
+    if write {
+        scopes.extend(&[Scope::Upload, Scope::Review]);
+    }
+    if write || scopes.is_empty() {
+        scopes.push(Scope::Read);
+    }
+    scopes.sort_by_key(|s| Scope::ALL.iter().position(|x| x == s));
+    scopes.dedup();
+    let pepper = require_pepper(pepper)?;
+    if matches!(expires_in_days, Some(days) if days < 0) {
+        failure::bail!("expiry must not be negative");
+    }
+    let expires_at = expires_in_days.map(|days| Utc::now() + Duration::days(days));
+    let key = gen_api_key(&db, note.as_deref(), &scopes, expires_at, quota, &pepper)?;
     println!("{}", key);
     Ok(())
 }
 
 fn ls_api(db: PgConnection) -> Result<(), failure::Error> {
-    let apis = api_keys::table.load::<ApiKey>(&db)?;
+    let apis = api_keys::table
+        .order(api_keys::created_at)
+        .load::<ApiKey>(&db)?;
     for key in apis {
-        print!("id={} write_access={}", key.id, key.write_access);
+        print!(
+            "id={} prefix={} scopes={} created={} uses={}",
+            key.id,
+            key.prefix.as_deref().unwrap_or("(legacy)"),
+            key.scopes.join(","),
+            key.created_at.to_rfc3339(),
+            key.use_count
+        );
+        if let Some(quota) = key.daily_quota {
+            print!(" daily_quota={}", quota);
+        }
+        if let Some(last_used_at) = key.last_used_at {
+            print!(" last_used={}", last_used_at.to_rfc3339());
+        }
+        if let Some(expires_at) = key.expires_at {
+            print!(" expires={}", expires_at.to_rfc3339());
+        }
+        if let Some(revoked_at) = key.revoked_at {
+            print!(" revoked={}", revoked_at.to_rfc3339());
+        }
         if let Some(note) = key.note {
             print!(" (note: {})", note);
         }
@@ -80,6 +216,227 @@ fn rm_api(db: PgConnection, id: Uuid) -> Result<(), failure::Error> {
     Ok(())
 }
 
+fn rotate_api(
+    db: PgConnection,
+    pepper: Option<String>,
+    id: Uuid,
+    grace_hours: i64,
+) -> Result<(), failure::Error> {
+    if grace_hours < 0 {
+        failure::bail!("grace period must not be negative");
+    }
+    let pepper = require_pepper(pepper)?;
+    let key = rotate_api_key(&db, id, Duration::hours(grace_hours), &pepper)?
+        .ok_or_else(|| failure::format_err!("no active api key with id {}", id))?;
+    println!("{}", key);
+    Ok(())
+}
+
+fn revoke_api(db: PgConnection, id: Uuid) -> Result<(), failure::Error> {
+    if !revoke_api_key(&db, id)? {
+        failure::bail!("no active api key with id {}", id);
+    }
+    Ok(())
+}
+
+fn set_quota(db: PgConnection, id: Uuid, quota: Option<i32>) -> Result<(), failure::Error> {
+    if !set_api_key_quota(&db, id, quota)? {
+        failure::bail!("no api key with id {}", id);
+    }
+    Ok(())
+}
+
+fn purge_key(db: PgConnection, id: Uuid) -> Result<(), failure::Error> {
+    let purged = purge_api_key_rows(&db, id)?;
+    println!(
+        "Deleted {} answers, {} proposals, {} answer strings and {} question strings, restored {} answers",
+        purged.answers,
+        purged.proposals,
+        purged.answer_strings,
+        purged.question_strings,
+        purged.restored_answers
+    );
+    Ok(())
+}
+
+fn proposals(db: PgConnection, question: Option<i32>) -> Result<(), failure::Error> {
+    let mut query = answer_proposals::table
+        .inner_join(answers::table)
+        .inner_join(
+            question_strings::table.on(question_strings::question_id.eq(answers::question_id)),
+        )
+        .select((
+            answer_proposals::all_columns,
+            answers::answer_used,
+            question_strings::question_string,
+        ))
+        .into_boxed();
+    if let Some(question) = question {
+        query = query.filter(answer_proposals::question_id.eq(question));
+    }
+    let proposals = query
+        .order((answer_proposals::question_id, answer_proposals::created_at))
+        .load::<(AnswerProposal, i32, String)>(&db)?;
+    let answer_ids = proposals
+        .iter()
+        .flat_map(|(p, current, _)| vec![p.answer_used, *current])
+        .collect::<Vec<_>>();
+    let answer_text = answer_strings::table
+        .filter(answer_strings::answer_id.eq(any(&answer_ids)))
+        .select((answer_strings::answer_id, answer_strings::answer_string))
+        .load::<(i32, String)>(&db)?
+        .into_iter()
+        .map(|(k, v)| (k, process_answer(&v)))
+        .collect::<HashMap<i32, String>>();
+    let text = |id: &i32| answer_text.get(id).map(|x| &x[..]).unwrap_or("?");
+    for (proposal, current, question) in &proposals {
+        println!(
+            "Proposal {} for question {} ({}):",
+            proposal.id,
+            proposal.question_id,
+            process_question(question)
+        );
+        println!("  Reviewed: {} ({})", current, text(current));
+        println!(
+            "  Proposed: {} ({})",
+            proposal.answer_used,
+            text(&proposal.answer_used)
+        );
+        let key = proposal
+            .submitted_by
+            .map(|id| id.to_string())
+            .unwrap_or_else(|| "(removed)".to_owned());
+        println!("  By {} at {}", key, proposal.created_at.to_rfc3339());
+    }
+    Ok(())
+}
+
+fn accept(db: PgConnection, id: i64) -> Result<(), failure::Error> {
+    if !accept_proposal(&db, id)? {
+        failure::bail!("no proposal with id {}", id);
+    }
+    Ok(())
+}
+
+fn reject(db: PgConnection, id: i64) -> Result<(), failure::Error> {
+    if !reject_proposal(&db, id)? {
+        failure::bail!("no proposal with id {}", id);
+    }
+    Ok(())
+}
+
+/// Formats an `old -> new` pair of revision fields.
+fn change<T: Debug + PartialEq>(old: &Option<T>, new: &Option<T>) -> String {
+    let show = |x: &Option<T>| match x {
+        Some(x) => format!("{:?}", x),
+        None => "-".to_owned(),
+    };
+    if old == new {
+        show(new)
+    } else {
+        format!("{} -> {}", show(old), show(new))
+    }
+}
+
+fn history(db: PgConnection, question_id: i32) -> Result<(), failure::Error> {
+    let revisions = answer_revisions::table
+        .filter(answer_revisions::question_id.eq(question_id))
+        .order((answer_revisions::created_at, answer_revisions::id))
+        .load::<AnswerRevision>(&db)?;
+    for rev in revisions {
+        let by = match (rev.actor_kind.as_str(), rev.key_id) {
+            ("admin", _) => "admin".to_owned(),
+            (_, Some(id)) => format!("key {}", id),
+            (_, None) => "(deleted key)".to_owned(),
+        };
+        println!(
+            "{} {} {} by {}",
+            rev.id,
+            rev.created_at.to_rfc3339(),
+            rev.source,
+            by
+        );
+        println!(
+            "  answer_used: {}",
+            change(&rev.old_answer_used, &rev.new_answer_used)
+        );
+        println!(
+            "  reviewed: {}",
+            change(&rev.old_reviewed, &rev.new_reviewed)
+        );
+        println!(
+            "  valid_answers: {}",
+            change(&rev.old_valid_answers, &rev.new_valid_answers)
+        );
+        if rev.old_test != rev.new_test {
+            println!("  test: {}", change(&rev.old_test, &rev.new_test));
+        }
+        if rev.old_submitted_by != rev.new_submitted_by {
+            println!(
+                "  submitted_by: {}",
+                change(&rev.old_submitted_by, &rev.new_submitted_by)
+            );
+        }
+    }
+    Ok(())
+}
+
+fn revert(db: PgConnection, id: i64) -> Result<(), failure::Error> {
+    if !revert_revision(&db, id)? {
+        failure::bail!("no revision with id {}", id);
+    }
+    Ok(())
+}
+
+fn audit(
+    db: PgConnection,
+    key: Option<Uuid>,
+    since: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>,
+    question: Option<i32>,
+    limit: i64,
+) -> Result<(), failure::Error> {
+    let mut query = audit_log::table.into_boxed();
+    if let Some(key) = key {
+        query = query.filter(audit_log::key_id.eq(key));
+    }
+    if let Some(since) = since {
+        query = query.filter(audit_log::created_at.ge(since));
+    }
+    if let Some(until) = until {
+        query = query.filter(audit_log::created_at.lt(until));
+    }
+    if let Some(question) = question {
+        query = query.filter(audit_log::question_ids.contains(vec![question]));
+    }
+    let entries = query
+        .order(audit_log::created_at.desc())
+        .limit(limit)
+        .load::<AuditEntry>(&db)?;
+    for entry in entries {
+        let key_id = entry
+            .key_id
+            .map(|id| id.to_string())
+            .unwrap_or_else(|| "(removed)".to_owned());
+        let question_ids = entry
+            .question_ids
+            .iter()
+            .map(|id| id.to_string())
+            .collect::<Vec<_>>();
+        println!(
+            "{} {} key={} ip={} answers={} strings={} questions={}",
+            entry.created_at.to_rfc3339(),
+            entry.endpoint,
+            key_id,
+            entry.client_ip.as_deref().unwrap_or("-"),
+            entry.answer_rows,
+            entry.string_rows,
+            question_ids.join(",")
+        );
+    }
+    Ok(())
+}
+
 fn main() -> Result<(), ExitFailure> {
     let _ = dotenv();
     let args = Args::from_args();
@@ -90,9 +447,41 @@ fn main() -> Result<(), ExitFailure> {
         Command::ViewData => view(db)?,
         Command::DeleteQuestion { id } => del_question(db, id)?,
         Command::Dump => dump(db)?,
-        Command::ApiKey { write, note } => new_api_key(db, write, note)?,
+        Command::ApiKey {
+            scopes,
+            write,
+            note,
+            expires_in_days,
+            quota,
+        } => new_api_key(
+            db,
+            args.api_key_pepper,
+            scopes,
+            write,
+            note,
+            expires_in_days,
+            quota,
+        )?,
         Command::LsApi => ls_api(db)?,
         Command::RmApi { id } => rm_api(db, id)?,
+        Command::RotateApi { id, grace_hours } => {
+            rotate_api(db, args.api_key_pepper, id, grace_hours)?
+        }
+        Command::RevokeApi { id } => revoke_api(db, id)?,
+        Command::SetQuota { id, quota } => set_quota(db, id, quota)?,
+        Command::PurgeKey { id } => purge_key(db, id)?,
+        Command::Proposals { question } => proposals(db, question)?,
+        Command::AcceptProposal { id } => accept(db, id)?,
+        Command::RejectProposal { id } => reject(db, id)?,
+        Command::History { question_id } => history(db, question_id)?,
+        Command::Revert { id } => revert(db, id)?,
+        Command::Audit {
+            key,
+            since,
+            until,
+            question,
+            limit,
+        } => audit(db, key, since, until, question, limit)?,
     }
     Ok(())
 }
@@ -130,7 +519,7 @@ fn dump(db: PgConnection) -> Result<(), failure::Error> {
 }
 
 fn del_question(db: PgConnection, id: i32) -> Result<(), failure::Error> {
-    diesel::delete(answers::table.find(id)).execute(&db)?;
+    delete_answer(&db, id)?;
     Ok(())
 }
 
@@ -182,6 +571,7 @@ fn view(db: PgConnection) -> Result<(), failure::Error> {
     println!("Questions:");
     let answer_cache = answer_strings::table
         .filter(answer_strings::answer_id.eq(any(&all_ans_ids)))
+        .select((answer_strings::answer_id, answer_strings::answer_string))
         .load(&db)?
         .into_iter()
         .map(|(k, v): (i32, String)| (k, process_answer(&v)))
@@ -250,6 +640,7 @@ fn review(db: PgConnection) -> Result<(), failure::Error> {
     }
     let answer_text_cache = answer_strings::table
         .filter(answer_strings::answer_id.eq(any(&all_ans_ids)))
+        .select((answer_strings::answer_id, answer_strings::answer_string))
         .load::<(i32, String)>(&db)?
         .into_iter()
         .collect::<HashMap<i32, String>>();
@@ -281,17 +672,13 @@ fn review(db: PgConnection) -> Result<(), failure::Error> {
             stdin().read_line(&mut input)?;
             let input = input.trim().parse::<u8>()?;
             if input == 0 {
-                diesel::update(answers::table.find(answer.question_id))
-                    .set(answers::reviewed.eq(true))
-                    .execute(&db)?;
+                review_answer(&db, answer.question_id, None)?;
             } else if input == 1 {
                 println!("Enter DB ID:");
                 let mut input = String::new();
                 stdin().read_line(&mut input)?;
                 let input = input.trim().parse::<i32>()?;
-                diesel::update(answers::table.find(answer.question_id))
-                    .set((answers::answer_used.eq(input), answers::reviewed.eq(true)))
-                    .execute(&db)?;
+                review_answer(&db, answer.question_id, Some(input))?;
                 println!(
                     "Updated question {} ({}) to {} ({})",
                     answer.question_id,
@@ -303,7 +690,7 @@ fn review(db: PgConnection) -> Result<(), failure::Error> {
                         .unwrap_or("INVALID ANSWER ID")
                 );
             } else if input == 2 {
-                diesel::delete(answers::table.find(answer.question_id)).execute(&db)?;
+                delete_answer(&db, answer.question_id)?;
             } else {
                 continue;
             }
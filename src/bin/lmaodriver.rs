@@ -1,6 +1,9 @@
 use dotenv::dotenv;
 use lmaobgd::display::*;
 use lmaobgd::models::*;
+use lmaobgd::page_object;
+use lmaobgd::webdriver::batch::ReadSpec;
+use lmaobgd::webdriver::locator::Locator;
 use lmaobgd::webdriver::*;
 use rand::prelude::*;
 use reqwest::Client;
@@ -31,6 +34,29 @@ fn num_list(data: &str) -> Vec<usize> {
         .collect()
 }
 
+page_object! {
+    struct LoginPage {
+        username: Locator::css("#UserName"),
+        password: Locator::css("#Password"),
+        submit: Locator::css("#AjaxLogin"),
+    }
+}
+
+page_object! {
+    struct TestPage {
+        start: Locator::css("#start-test"),
+        title: Locator::css("body .row .col-12 h1"),
+        questions: [Locator::css(".question-box")],
+    }
+}
+
+page_object! {
+    struct ResultPage {
+        correct: Locator::css("#lblTrueAnswer"),
+        wrong: Locator::css("#lblFalseAnser"), // intentional typo
+    }
+}
+
 /// LmaoBGD WebDriver
 #[derive(StructOpt)]
 struct Args {
@@ -58,7 +84,7 @@ struct Args {
     /// Repeat auto review until all correct answer.
     #[structopt(short, long)]
     crack: bool,
-    /// Verbose mode
+    /// Enable trace level geckodriver logs (filter with RUST_LOG)
     #[structopt(short, long)]
     verbose: bool,
     /// Geckodriver command to use
@@ -75,6 +101,7 @@ struct Args {
 
 #[tokio::main]
 async fn main() -> Result<(), exitfailure::ExitFailure> {
+    env_logger::init();
     let _ = dotenv();
     let args = Args::from_args();
     let wd = WebDriver::new_firefox(args.geckodriver.as_ref(), args.headless, args.verbose).await?;
@@ -84,12 +111,12 @@ async fn main() -> Result<(), exitfailure::ExitFailure> {
     let main = async {
         wd.navigate("http://study.hanoi.edu.vn/dang-nhap?returnUrl=/")
             .await?;
-        let username = wd.get_element(Using::CssSelector, "#UserName").await?;
-        let password = wd.get_element(Using::CssSelector, "#Password").await?;
-        wd.element_send_keys(&username, &args.id).await?;
-        wd.element_send_keys(&password, password_txt).await?;
-        let button = wd.get_element(Using::CssSelector, "#AjaxLogin").await?;
-        wd.element_click(&button).await?;
+        let login = LoginPage::new(&wd);
+        wd.element_send_keys(&login.username().await?, &args.id)
+            .await?;
+        wd.element_send_keys(&login.password().await?, password_txt)
+            .await?;
+        wd.element_click(&login.submit().await?).await?;
 
         while run(&wd, &args, &args.api_url, &args.api_key, &client).await? {
             // repeat
@@ -125,69 +152,63 @@ async fn run(
     let data_url = format!("{}/data", api);
     let data = client
         .get(&data_url)
-        .basic_auth(key, None::<&str>)
+        .bearer_auth(key)
         .send()
         .await?
         .error_for_status()?
         .json::<HashMap<i32, i32>>()
         .await?;
     wd.navigate(test_url).await?;
-    let start = wd.get_element(Using::CssSelector, "#start-test").await?;
-    wd.element_click(&start).await?;
-    let title_elem = wd
-        .get_element(Using::CssSelector, "body .row .col-12 h1")
-        .await?;
-    let title = wd.get_element_text(&title_elem).await?;
+    let page = TestPage::new(wd);
+    wd.element_click(&page.start().await?).await?;
+    let title = wd.get_element_text(&page.title().await?).await?;
     println!("Test name: {}", title);
-    let questions = wd.get_elements(Using::CssSelector, ".question-box").await?;
+    let questions = page.questions().await?;
+    let question_data = wd
+        .read_elements(&questions, &ReadSpec::new().attr("data-id").text())
+        .await?;
+    let input_spec = ReadSpec::new()
+        .prop("value")
+        .prop("parentNode.parentNode.innerText");
     let mut question_maps = HashMap::new();
     let mut answer_of_questions = HashMap::new();
     let mut answer_maps = HashMap::new();
     let mut unknowns = HashMap::new();
     let mut question_ids = Vec::new();
-    for question in questions {
-        let q_id = wd
-            .get_element_attr(&question, "data-id")
-            .await?
+    for (question, q_data) in questions.iter().zip(question_data) {
+        let q_id = q_data
+            .attr("data-id")
+            .ok_or_else(|| failure::format_err!("question without data-id"))?
             .parse::<i32>()?;
         question_ids.push(q_id);
         let cur_answer = data.get(&q_id).copied();
         if args.force_fetch || cur_answer.is_none() {
-            let q_text = wd.get_element_text(&question).await?;
+            let q_text = q_data.text.unwrap_or_default();
             println!("Question {}: {}", q_id, process_question(&q_text));
             question_maps.insert(q_id, q_text);
         }
-        let inputs = wd
-            .get_elements_from_element(&question, Using::CssSelector, r#"input[type="radio"]"#)
-            .await?;
+        let inputs = wd.radio_inputs(question).await?;
+        let input_data = wd.read_elements(&inputs, &input_spec).await?;
         let mut answers = Vec::new();
         let mut input_elems = Vec::new();
         let mut answered = false;
-        for input in inputs {
+        for (input, a_data) in inputs.into_iter().zip(input_data) {
             input_elems.push(input.clone());
-            let a_id = wd
-                .get_element_prop::<String>(&input, "value")
-                .await?
-                .parse::<i32>()?;
+            let a_id = a_data.prop::<String>("value")?.parse::<i32>()?;
             if cur_answer.is_none() || args.force_fetch {
-                let a_text = wd
-                    .run_script_elem(
-                        "return arguments[0].parentNode.parentNode.innerText;",
-                        &input,
-                    )
-                    .await?;
+                let a_text = a_data.prop("parentNode.parentNode.innerText")?;
                 answer_maps.insert(a_id, a_text);
             }
             answers.push(a_id);
             if cur_answer == Some(a_id) {
-                wd.element_send_keys(&input, "\u{e00d}").await?;
+                wd.check_radio(&input).await?;
                 answered = true;
             }
         }
         if !answered {
             let idx = rand::thread_rng().gen_range(0, answers.len());
             unknowns.insert(q_id, answers[idx]);
-            wd.element_send_keys(&input_elems[idx], "\u{e00d}").await?;
+            wd.check_radio(&input_elems[idx]).await?;
         }
 
         answer_of_questions.insert(q_id, answers);
@@ -199,12 +220,9 @@ async fn run(
         println!("Waiting for result page...");
         delay_for(Duration::from_secs(15)).await;
         if args.autoreview {
-            let correct_e = wd.get_element(Using::CssSelector, "#lblTrueAnswer").await?;
-            let wrong = wd
-                .get_element(Using::CssSelector, "#lblFalseAnser") // intentional typo
-                .await?;
-            let correct_t = wd.get_element_text(&correct_e).await?;
-            let wrong = wd.get_element_text(&wrong).await?;
+            let result = ResultPage::new(wd);
+            let correct_t = wd.get_element_text(&result.correct().await?).await?;
+            let wrong = wd.get_element_text(&result.wrong().await?).await?;
             correct = Some(
                 num_list(&correct_t)
                     .into_iter()
@@ -242,7 +260,7 @@ async fn run(
     let upload_url = format!("{}/upload", api);
     client
         .post(&upload_url)
-        .basic_auth(key, None::<&str>)
+        .bearer_auth(key)
         .json(&js_api_data)
         .send()
         .await?
@@ -251,7 +269,7 @@ async fn run(
         let set_correct_url = format!("{}/set_reviewed", api);
         client
             .post(&set_correct_url)
-            .basic_auth(key, None::<&str>)
+            .bearer_auth(key)
             .json(&correct)
             .send()
             .await?
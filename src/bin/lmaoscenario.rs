@@ -0,0 +1,515 @@
+use dotenv::dotenv;
+use failure::ResultExt;
+use lmaobgd::webdriver::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{stdout, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use structopt::StructOpt;
+use tokio::time::delay_for;
+
+/// LmaoBGD scenario runner
+///
+/// Runs browser scenarios described in YAML or JSON files and reports the
+/// result of every step.
+#[derive(StructOpt)]
+struct Args {
+    /// Geckodriver command to use
+    #[structopt(short, long)]
+    geckodriver: Option<String>,
+    /// Headless mode
+    #[structopt(short = "H", long)]
+    headless: bool,
+    /// Enable trace level geckodriver logs (filter with RUST_LOG)
+    #[structopt(short, long)]
+    verbose: bool,
+    /// Report format (junit or json)
+    #[structopt(short, long, default_value = "junit")]
+    format: ReportFormat,
+    /// Write the report to a file instead of stdout
+    #[structopt(short, long)]
+    output: Option<PathBuf>,
+    /// Set a variable, as name=value
+    #[structopt(short = "V", long = "var", parse(try_from_str = parse_var))]
+    vars: Vec<(String, String)>,
+    /// Scenario files to run
+    #[structopt(required = true)]
+    scenarios: Vec<PathBuf>,
+}
+
+enum ReportFormat {
+    Junit,
+    Json,
+}
+
+impl std::str::FromStr for ReportFormat {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "junit" => Ok(ReportFormat::Junit),
+            "json" => Ok(ReportFormat::Json),
+            _ => failure::bail!("unknown report format {}", s),
+        }
+    }
+}
+
+fn parse_var(s: &str) -> Result<(String, String), failure::Error> {
+    let idx = s
+        .find('=')
+        .ok_or_else(|| failure::format_err!("expected name=value, got {}", s))?;
+    Ok((s[..idx].to_string(), s[idx + 1..].to_string()))
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Scenario {
+    name: Option<String>,
+    /// Default timeout in seconds for element lookups
+    #[serde(default = "default_timeout")]
+    timeout: f64,
+    #[serde(default)]
+    vars: HashMap<String, String>,
+    steps: Vec<Step>,
+}
+
+fn default_timeout() -> f64 {
+    10.0
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+enum Step {
+    Navigate(String),
+    Find(Target),
+    Click(Target),
+    Type {
+        #[serde(flatten)]
+        target: Target,
+        text: String,
+        #[serde(default)]
+        submit: bool,
+    },
+    Wait(Wait),
+    AssertText {
+        #[serde(flatten)]
+        target: Target,
+        equals: Option<String>,
+        contains: Option<String>,
+    },
+    Screenshot {
+        path: PathBuf,
+    },
+    Extract {
+        #[serde(flatten)]
+        target: Target,
+        var: String,
+        attribute: Option<String>,
+    },
+}
+
+#[derive(Deserialize)]
+#[serde(untagged, deny_unknown_fields)]
+enum Wait {
+    Seconds { seconds: f64 },
+    Element(Target),
+}
+
+#[derive(Deserialize)]
+#[serde(try_from = "RawTarget")]
+struct Target {
+    by: By,
+    /// Timeout in seconds, overriding the scenario default
+    timeout: Option<f64>,
+}
+
+/// Selector fields as written in the scenario file.
+///
+/// `By` is not flattened directly so that steps can deny unknown fields.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawTarget {
+    css: Option<String>,
+    xpath: Option<String>,
+    link_text: Option<String>,
+    partial_link_text: Option<String>,
+    tag_name: Option<String>,
+    timeout: Option<f64>,
+}
+
+impl std::convert::TryFrom<RawTarget> for Target {
+    type Error = String;
+
+    fn try_from(raw: RawTarget) -> Result<Self, Self::Error> {
+        let mut by = vec![];
+        by.extend(raw.css.map(By::Css));
+        by.extend(raw.xpath.map(By::Xpath));
+        by.extend(raw.link_text.map(By::LinkText));
+        by.extend(raw.partial_link_text.map(By::PartialLinkText));
+        by.extend(raw.tag_name.map(By::TagName));
+        if by.len() != 1 {
+            return Err(
+                "expected exactly one of css, xpath, link_text, partial_link_text or tag_name"
+                    .to_string(),
+            );
+        }
+        Ok(Target {
+            by: by.remove(0),
+            timeout: raw.timeout,
+        })
+    }
+}
+
+enum By {
+    Css(String),
+    Xpath(String),
+    LinkText(String),
+    PartialLinkText(String),
+    TagName(String),
+}
+
+impl By {
+    fn using(&self) -> (Using, &str) {
+        match self {
+            By::Css(v) => (Using::CssSelector, v),
+            By::Xpath(v) => (Using::XPath, v),
+            By::LinkText(v) => (Using::LinkText, v),
+            By::PartialLinkText(v) => (Using::PartialLinkText, v),
+            By::TagName(v) => (Using::TagName, v),
+        }
+    }
+}
+
+impl std::fmt::Display for By {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            By::Css(v) => write!(f, "css {:?}", v),
+            By::Xpath(v) => write!(f, "xpath {:?}", v),
+            By::LinkText(v) => write!(f, "link text {:?}", v),
+            By::PartialLinkText(v) => write!(f, "partial link text {:?}", v),
+            By::TagName(v) => write!(f, "tag {:?}", v),
+        }
+    }
+}
+
+impl Step {
+    fn describe(&self) -> String {
+        match self {
+            Step::Navigate(url) => format!("navigate {}", url),
+            Step::Find(t) => format!("find {}", t.by),
+            Step::Click(t) => format!("click {}", t.by),
+            Step::Type { target, .. } => format!("type into {}", target.by),
+            Step::Wait(Wait::Seconds { seconds }) => format!("wait {}s", seconds),
+            Step::Wait(Wait::Element(t)) => format!("wait for {}", t.by),
+            Step::AssertText { target, .. } => format!("assert text of {}", target.by),
+            Step::Screenshot { path } => format!("screenshot {}", path.display()),
+            Step::Extract { target, var, .. } => format!("extract {} into {}", target.by, var),
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Status {
+    Passed,
+    Failed,
+    Skipped,
+}
+
+#[derive(Serialize)]
+struct StepResult {
+    name: String,
+    status: Status,
+    seconds: f64,
+    message: Option<String>,
+}
+
+#[derive(Serialize)]
+struct ScenarioResult {
+    name: String,
+    file: PathBuf,
+    seconds: f64,
+    steps: Vec<StepResult>,
+}
+
+impl ScenarioResult {
+    fn count(&self, f: impl Fn(&Status) -> bool) -> usize {
+        self.steps.iter().filter(|s| f(&s.status)).count()
+    }
+}
+
+fn load_scenario(path: &Path) -> Result<Scenario, failure::Error> {
+    let file = File::open(path).with_context(|_| format!("unable to open {}", path.display()))?;
+    let scenario = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("json") => serde_json::from_reader(file)?,
+        _ => serde_yaml::from_reader(file)?,
+    };
+    Ok(scenario)
+}
+
+/// Replaces `${name}` with the value of variable `name`.
+fn interpolate(s: &str, vars: &HashMap<String, String>) -> Result<String, failure::Error> {
+    let mut result = String::new();
+    let mut rest = s;
+    while let Some(start) = rest.find("${") {
+        result.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| failure::format_err!("unterminated variable in {:?}", s))?;
+        let name = &rest[start + 2..start + end];
+        let value = vars
+            .get(name)
+            .ok_or_else(|| failure::format_err!("undefined variable {}", name))?;
+        result.push_str(value);
+        rest = &rest[start + end + 1..];
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+/// Longest duration accepted for timeouts and waits, one day.
+const MAX_SECONDS: f64 = 86400.0;
+
+fn seconds(secs: f64) -> Duration {
+    if secs.is_nan() || secs <= 0.0 {
+        return Duration::from_secs(0);
+    }
+    Duration::from_secs_f64(secs.min(MAX_SECONDS))
+}
+
+async fn find(
+    wd: &WebDriver,
+    target: &Target,
+    scenario: &Scenario,
+    vars: &HashMap<String, String>,
+) -> Result<WebElement, failure::Error> {
+    let (using, value) = target.by.using();
+    let value = interpolate(value, vars)?;
+    let timeout = seconds(target.timeout.unwrap_or(scenario.timeout));
+    wd.wait_for_element(using, value, timeout)
+        .await
+        .with_context(|_| format!("unable to find {}", target.by))
+        .map_err(Into::into)
+}
+
+async fn run_step(
+    wd: &WebDriver,
+    step: &Step,
+    scenario: &Scenario,
+    vars: &mut HashMap<String, String>,
+) -> Result<(), failure::Error> {
+    match step {
+        Step::Navigate(url) => wd.navigate(interpolate(url, vars)?).await?,
+        Step::Find(target) => {
+            find(wd, target, scenario, vars).await?;
+        }
+        Step::Click(target) => {
+            let elem = find(wd, target, scenario, vars).await?;
+            wd.element_click(&elem).await?;
+        }
+        Step::Type {
+            target,
+            text,
+            submit,
+        } => {
+            let elem = find(wd, target, scenario, vars).await?;
+            let mut text = interpolate(text, vars)?;
+            if *submit {
+                text.push('\u{e007}');
+            }
+            wd.element_send_keys(&elem, text).await?;
+        }
+        Step::Wait(Wait::Seconds { seconds: secs }) => delay_for(seconds(*secs)).await,
+        Step::Wait(Wait::Element(target)) => {
+            find(wd, target, scenario, vars).await?;
+        }
+        Step::AssertText {
+            target,
+            equals,
+            contains,
+        } => {
+            let elem = find(wd, target, scenario, vars).await?;
+            let text = wd.get_element_text(&elem).await?;
+            if let Some(expected) = equals {
+                let expected = interpolate(expected, vars)?;
+                if text.trim() != expected.trim() {
+                    failure::bail!("expected text {:?}, got {:?}", expected, text);
+                }
+            }
+            if let Some(expected) = contains {
+                let expected = interpolate(expected, vars)?;
+                if !text.contains(&expected) {
+                    failure::bail!("expected text containing {:?}, got {:?}", expected, text);
+                }
+            }
+        }
+        Step::Screenshot { path } => {
+            let png = wd.screenshot().await?;
+            File::create(path)?.write_all(&png)?;
+        }
+        Step::Extract {
+            target,
+            var,
+            attribute,
+        } => {
+            let elem = find(wd, target, scenario, vars).await?;
+            let value = match attribute {
+                Some(attr) => wd.get_element_attr(&elem, attr).await?,
+                None => wd.get_element_text(&elem).await?,
+            };
+            vars.insert(var.clone(), value);
+        }
+    }
+    Ok(())
+}
+
+async fn run_scenario(
+    wd: &WebDriver,
+    file: &Path,
+    scenario: &Scenario,
+    vars: &[(String, String)],
+) -> ScenarioResult {
+    let start = Instant::now();
+    let mut vars = scenario
+        .vars
+        .iter()
+        .map(|(k, v)| (k.clone(), v.clone()))
+        .chain(vars.iter().cloned())
+        .collect::<HashMap<_, _>>();
+    let mut steps = Vec::new();
+    let mut failed = false;
+    for step in &scenario.steps {
+        let name = step.describe();
+        if failed {
+            steps.push(StepResult {
+                name,
+                status: Status::Skipped,
+                seconds: 0.0,
+                message: None,
+            });
+            continue;
+        }
+        let step_start = Instant::now();
+        let ret = run_step(wd, step, scenario, &mut vars).await;
+        let (status, message) = match ret {
+            Ok(()) => (Status::Passed, None),
+            Err(e) => {
+                failed = true;
+                (Status::Failed, Some(e.to_string()))
+            }
+        };
+        steps.push(StepResult {
+            name,
+            status,
+            seconds: step_start.elapsed().as_secs_f64(),
+            message,
+        });
+    }
+    ScenarioResult {
+        name: scenario
+            .name
+            .clone()
+            .unwrap_or_else(|| file.display().to_string()),
+        file: file.to_owned(),
+        seconds: start.elapsed().as_secs_f64(),
+        steps,
+    }
+}
+
+fn xml_escape(s: &str) -> String {
+    let mut result = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => result.push_str("&amp;"),
+            '<' => result.push_str("&lt;"),
+            '>' => result.push_str("&gt;"),
+            '"' => result.push_str("&quot;"),
+            '\'' => result.push_str("&apos;"),
+            c => result.push(c),
+        }
+    }
+    result
+}
+
+fn write_junit(out: &mut dyn Write, results: &[ScenarioResult]) -> std::io::Result<()> {
+    writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(out, "<testsuites>")?;
+    for result in results {
+        writeln!(
+            out,
+            r#"  <testsuite name="{}" tests="{}" failures="{}" skipped="{}" time="{:.3}">"#,
+            xml_escape(&result.name),
+            result.steps.len(),
+            result.count(|s| matches!(s, Status::Failed)),
+            result.count(|s| matches!(s, Status::Skipped)),
+            result.seconds,
+        )?;
+        for (idx, step) in result.steps.iter().enumerate() {
+            write!(
+                out,
+                r#"    <testcase name="{}. {}" classname="{}" time="{:.3}""#,
+                idx + 1,
+                xml_escape(&step.name),
+                xml_escape(&result.name),
+                step.seconds,
+            )?;
+            match step.status {
+                Status::Passed => writeln!(out, "/>")?,
+                Status::Skipped => writeln!(out, "><skipped/></testcase>")?,
+                Status::Failed => writeln!(
+                    out,
+                    r#"><failure message="{}"/></testcase>"#,
+                    xml_escape(step.message.as_deref().unwrap_or(""))
+                )?,
+            }
+        }
+        writeln!(out, "  </testsuite>")?;
+    }
+    writeln!(out, "</testsuites>")?;
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<(), exitfailure::ExitFailure> {
+    env_logger::init();
+    let _ = dotenv();
+    let args = Args::from_args();
+    let scenarios = args
+        .scenarios
+        .iter()
+        .map(|path| load_scenario(path).map(|s| (path, s)))
+        .collect::<Result<Vec<_>, _>>()?;
+    let wd = WebDriver::new_firefox(args.geckodriver.as_ref(), args.headless, args.verbose).await?;
+
+    let mut results = Vec::new();
+    for (path, scenario) in &scenarios {
+        wd.delete_all_cookies().await?;
+        results.push(run_scenario(&wd, path, scenario, &args.vars).await);
+    }
+    wd.close().await?;
+
+    let mut out: Box<dyn Write> = match &args.output {
+        Some(path) => Box::new(File::create(path)?),
+        None => Box::new(stdout()),
+    };
+    match args.format {
+        ReportFormat::Junit => write_junit(&mut out, &results)?,
+        ReportFormat::Json => {
+            serde_json::to_writer_pretty(&mut out, &results)?;
+            writeln!(out)?;
+        }
+    }
+
+    let failed = results
+        .iter()
+        .filter(|r| r.count(|s| matches!(s, Status::Failed)) > 0)
+        .count();
+    if failed > 0 {
+        return Err(
+            failure::format_err!("{} of {} scenarios failed", failed, results.len()).into(),
+        );
+    }
+    Ok(())
+}
@@ -1,8 +1,11 @@
 #[macro_use]
 extern crate diesel;
+#[doc(hidden)]
+pub use paste;
 
 pub mod actions;
 pub mod models;
+pub mod ratelimit;
 #[rustfmt::skip]
 pub mod schema;
 pub mod display;
@@ -1,6 +1,9 @@
 use crate::schema::*;
+use chrono::{DateTime, NaiveDate, Utc};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
 use uuid::Uuid;
 
 #[derive(Insertable, Queryable, Clone)]
@@ -11,18 +14,91 @@ pub struct Answer {
     pub reviewed: bool,
     pub test: Uuid,
     pub valid_answers: Vec<i32>,
+    /// API key that last uploaded the answer
+    pub submitted_by: Option<Uuid>,
+}
+
+/// An uploaded answer that disagrees with a reviewed one, kept for an
+/// admin to accept or reject.
+#[derive(Queryable, Clone)]
+pub struct AnswerProposal {
+    pub id: i64,
+    pub question_id: i32,
+    pub answer_used: i32,
+    pub valid_answers: Vec<i32>,
+    pub test: Uuid,
+    pub submitted_by: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Insertable)]
+#[table_name = "answer_proposals"]
+pub struct NewAnswerProposal {
+    pub question_id: i32,
+    pub answer_used: i32,
+    pub valid_answers: Vec<i32>,
+    pub test: Uuid,
+    pub submitted_by: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A change to a row of `answers`. The old fields are `None` when the
+/// row was created and the new fields when it was deleted.
+#[derive(Queryable, Clone)]
+pub struct AnswerRevision {
+    pub id: i64,
+    pub question_id: i32,
+    pub old_answer_used: Option<i32>,
+    pub new_answer_used: Option<i32>,
+    pub old_reviewed: Option<bool>,
+    pub new_reviewed: Option<bool>,
+    pub old_valid_answers: Option<Vec<i32>>,
+    pub new_valid_answers: Option<Vec<i32>>,
+    pub old_test: Option<Uuid>,
+    pub new_test: Option<Uuid>,
+    /// API key that made the change, `None` for admin commands or if the
+    /// key was removed since
+    pub key_id: Option<Uuid>,
+    /// Endpoint or command that made the change
+    pub source: String,
+    pub created_at: DateTime<Utc>,
+    pub old_submitted_by: Option<Uuid>,
+    pub new_submitted_by: Option<Uuid>,
+    /// `api_key` or `admin`
+    pub actor_kind: String,
+}
+
+#[derive(Insertable)]
+#[table_name = "answer_revisions"]
+pub struct NewAnswerRevision<'a> {
+    pub question_id: i32,
+    pub old_answer_used: Option<i32>,
+    pub new_answer_used: Option<i32>,
+    pub old_reviewed: Option<bool>,
+    pub new_reviewed: Option<bool>,
+    pub old_valid_answers: Option<Vec<i32>>,
+    pub new_valid_answers: Option<Vec<i32>>,
+    pub old_test: Option<Uuid>,
+    pub new_test: Option<Uuid>,
+    pub key_id: Option<Uuid>,
+    pub source: &'a str,
+    pub old_submitted_by: Option<Uuid>,
+    pub new_submitted_by: Option<Uuid>,
+    pub actor_kind: &'a str,
 }
 
 #[derive(Queryable, Clone)]
 pub struct AnswerMap {
     pub answer_id: i32,
     pub answer_string: String,
+    pub submitted_by: Option<Uuid>,
 }
 
 #[derive(Queryable, Clone)]
 pub struct QuestionMap {
     pub question_id: i32,
     pub question_string: String,
+    pub submitted_by: Option<Uuid>,
 }
 
 #[derive(Insertable)]
@@ -30,6 +106,7 @@ pub struct QuestionMap {
 pub struct NewAnswerMap<'a> {
     pub answer_id: i32,
     pub answer_string: &'a str,
+    pub submitted_by: Option<Uuid>,
 }
 
 #[derive(Insertable)]
@@ -37,6 +114,7 @@ pub struct NewAnswerMap<'a> {
 pub struct NewQuestionMap<'a> {
     pub question_id: i32,
     pub question_string: &'a str,
+    pub submitted_by: Option<Uuid>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -60,16 +138,124 @@ pub struct UnknownQuestion {
 pub struct ApiKey {
     pub id: Uuid,
     pub hash: Vec<u8>,
-    pub write_access: bool,
     pub note: Option<String>,
+    pub scopes: Vec<String>,
+    /// Lookup prefix, `None` for legacy keys stored as a plain digest
+    pub prefix: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+    /// Number of authenticated requests made with the key
+    pub use_count: i64,
+    /// Requests allowed per UTC day, unlimited if `None`
+    pub daily_quota: Option<i32>,
+}
+
+impl ApiKey {
+    /// Whether the key grants `scope`, either directly or through `admin`.
+    pub fn has_scope(&self, scope: Scope) -> bool {
+        self.scopes
+            .iter()
+            .any(|s| s == scope.as_str() || s == Scope::Admin.as_str())
+    }
 }
 
 #[derive(Insertable, Clone)]
 #[table_name = "api_keys"]
 pub struct NewApiKey<'a> {
     pub hash: &'a [u8],
-    pub write_access: bool,
     pub note: Option<&'a str>,
+    pub scopes: Vec<String>,
+    pub prefix: Option<&'a str>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub daily_quota: Option<i32>,
+}
+
+/// Permission granted to an API key.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Scope {
+    /// Fetch reviewed answers
+    Read,
+    /// Upload test results
+    Upload,
+    /// Mark answers as reviewed
+    Review,
+    /// Everything
+    Admin,
+}
+
+impl Scope {
+    pub const ALL: &'static [Scope] = &[Scope::Read, Scope::Upload, Scope::Review, Scope::Admin];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Scope::Read => "read",
+            Scope::Upload => "upload",
+            Scope::Review => "review",
+            Scope::Admin => "admin",
+        }
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Scope {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Scope::ALL
+            .iter()
+            .copied()
+            .find(|scope| scope.as_str() == s)
+            .ok_or_else(|| failure::format_err!("unknown scope {:?}", s))
+    }
+}
+
+/// Requests made with an API key in one UTC day that are not yet recorded.
+#[derive(Clone, Debug)]
+pub struct ApiKeyUse {
+    pub key_id: Uuid,
+    pub day: NaiveDate,
+    pub requests: i32,
+    pub last_used_at: DateTime<Utc>,
+}
+
+/// The API key making a change and where the request came from.
+#[derive(Clone, Debug)]
+pub struct Actor {
+    pub key_id: Uuid,
+    pub client_ip: Option<String>,
+}
+
+#[derive(Queryable, Clone)]
+pub struct AuditEntry {
+    pub id: i64,
+    /// Key that made the change, `None` if the key was removed since
+    pub key_id: Option<Uuid>,
+    pub endpoint: String,
+    pub created_at: DateTime<Utc>,
+    pub question_ids: Vec<i32>,
+    /// Number of rows written to `answers`
+    pub answer_rows: i32,
+    /// Number of rows written to the question and answer string tables
+    pub string_rows: i32,
+    pub client_ip: Option<String>,
+}
+
+#[derive(Insertable)]
+#[table_name = "audit_log"]
+pub struct NewAuditEntry<'a> {
+    pub key_id: Option<Uuid>,
+    pub endpoint: &'a str,
+    pub question_ids: Vec<i32>,
+    pub answer_rows: i32,
+    pub string_rows: i32,
+    pub client_ip: Option<&'a str>,
 }
 
 #[derive(Queryable, Eq, PartialEq, Ord, PartialOrd)]
@@ -0,0 +1,471 @@
+//! Token bucket rate limiting.
+
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::{header, HeaderMap};
+use actix_web::HttpResponse;
+use futures::future::{ok, Either, Ready};
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+/// Number of buckets kept before full buckets are dropped.
+const PRUNE_THRESHOLD: usize = 10_000;
+
+/// Shortest time between two prunes.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Most buckets kept. New keys are limited until buckets are pruned.
+const MAX_BUCKETS: usize = 100_000;
+
+/// A bucket of `requests` tokens refilled over `period`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rate {
+    pub requests: u32,
+    pub period: Duration,
+}
+
+impl Rate {
+    fn per_second(&self) -> f64 {
+        f64::from(self.requests) / self.period.as_secs_f64()
+    }
+}
+
+impl FromStr for Rate {
+    type Err = failure::Error;
+
+    /// Parses rates such as `30/m`, with `s`, `m`, `h` or `d` as the period.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(2, '/');
+        let requests = parts.next().unwrap_or_default().trim().parse::<u32>()?;
+        let period = match parts.next().map(str::trim) {
+            Some("s") => Duration::from_secs(1),
+            Some("m") => Duration::from_secs(60),
+            Some("h") => Duration::from_secs(60 * 60),
+            Some("d") => Duration::from_secs(24 * 60 * 60),
+            _ => failure::bail!("invalid rate {:?}, expected e.g. 30/m", s),
+        };
+        if requests == 0 {
+            failure::bail!("rate must allow at least one request");
+        }
+        Ok(Self { requests, period })
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    rate: Rate,
+}
+
+impl Bucket {
+    /// Returns the tokens in the bucket at `now`, up to its capacity.
+    fn tokens_at(&self, now: Instant) -> f64 {
+        let refill = (now - self.updated).as_secs_f64() * self.rate.per_second();
+        (self.tokens + refill).min(f64::from(self.rate.requests))
+    }
+}
+
+struct Buckets<K> {
+    map: HashMap<K, Bucket>,
+    pruned: Option<Instant>,
+}
+
+/// Token buckets keyed by `K`.
+pub struct RateLimiter<K> {
+    buckets: Mutex<Buckets<K>>,
+}
+
+impl<K: Hash + Eq> RateLimiter<K> {
+    pub fn new() -> Self {
+        Self {
+            buckets: Mutex::new(Buckets {
+                map: HashMap::new(),
+                pruned: None,
+            }),
+        }
+    }
+
+    /// Takes a token from the bucket of `key`, or returns how long to wait
+    /// until one is available.
+    pub fn check(&self, key: K, rate: &Rate) -> Result<(), Duration> {
+        self.check_at(key, rate, Instant::now())
+    }
+
+    fn check_at(&self, key: K, rate: &Rate, now: Instant) -> Result<(), Duration> {
+        let capacity = f64::from(rate.requests);
+        let mut buckets = self.buckets.lock().unwrap();
+        let Buckets { map, pruned } = &mut *buckets;
+        let prune_due = match pruned {
+            Some(pruned) => now.saturating_duration_since(*pruned) >= PRUNE_INTERVAL,
+            None => true,
+        };
+        if map.len() > PRUNE_THRESHOLD && prune_due {
+            map.retain(|_, b| b.tokens_at(now) < f64::from(b.rate.requests));
+            *pruned = Some(now);
+        }
+        if map.len() >= MAX_BUCKETS && !map.contains_key(&key) {
+            return Err(PRUNE_INTERVAL);
+        }
+        let bucket = map.entry(key).or_insert(Bucket {
+            tokens: capacity,
+            updated: now,
+            rate: *rate,
+        });
+        bucket.tokens = bucket.tokens_at(now);
+        bucket.updated = now;
+        if bucket.rate != *rate {
+            bucket.rate = *rate;
+            bucket.tokens = bucket.tokens.min(capacity);
+        }
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64(
+                (1.0 - bucket.tokens) / rate.per_second(),
+            ))
+        }
+    }
+}
+
+impl<K: Hash + Eq> Default for RateLimiter<K> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Returns a `429 Too Many Requests` response asking to retry after `wait`.
+pub fn too_many_requests(wait: Duration) -> HttpResponse {
+    let secs = wait.as_secs() + u64::from(wait.subsec_nanos() > 0);
+    HttpResponse::TooManyRequests()
+        .header(header::RETRY_AFTER, secs.max(1).to_string())
+        .finish()
+}
+
+/// The header a trusted reverse proxy adds the client address to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ProxyHeader {
+    Forwarded,
+    XForwardedFor,
+}
+
+impl FromStr for ProxyHeader {
+    type Err = failure::Error;
+
+    /// Parses `forwarded` or `x-forwarded-for`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "forwarded" => Ok(ProxyHeader::Forwarded),
+            "x-forwarded-for" => Ok(ProxyHeader::XForwardedFor),
+            _ => failure::bail!(
+                "invalid proxy header {:?}, expected forwarded or x-forwarded-for",
+                s
+            ),
+        }
+    }
+}
+
+/// Returns the address of the client given the request's headers and peer
+/// address.
+///
+/// With a `proxy_header`, the address is taken from the last entry of that
+/// header, which is the one added by the proxy in front of the server.
+/// Earlier entries and other headers come from the client and are ignored.
+pub fn client_ip(
+    headers: &HeaderMap,
+    peer_addr: Option<SocketAddr>,
+    proxy_header: Option<ProxyHeader>,
+) -> Option<IpAddr> {
+    match proxy_header {
+        None => peer_addr.map(|addr| addr.ip()),
+        Some(ProxyHeader::Forwarded) => {
+            let forwarded = last_entry(headers, &header::FORWARDED)?;
+            let remote = forwarded.split(';').find_map(|pair| {
+                let mut parts = pair.splitn(2, '=');
+                match parts.next()?.trim() {
+                    name if name.eq_ignore_ascii_case("for") => Some(parts.next()?.trim()),
+                    _ => None,
+                }
+            })?;
+            parse_remote(remote.trim_matches('"'))
+        }
+        Some(ProxyHeader::XForwardedFor) => {
+            let name = header::HeaderName::from_static("x-forwarded-for");
+            parse_remote(&last_entry(headers, &name)?)
+        }
+    }
+}
+
+/// Returns the last comma separated entry of the header `name`.
+fn last_entry(headers: &HeaderMap, name: &header::HeaderName) -> Option<String> {
+    let mut values = headers.get_all(name).collect::<Vec<_>>();
+    // actix-http 1.0 stores the second line of a repeated header first.
+    if values.len() >= 2 {
+        values.swap(0, 1);
+    }
+    let value = values.last()?.to_str().ok()?;
+    Some(value.rsplit(',').next()?.trim().to_owned())
+}
+
+/// Parses a forwarded client address, which may or may not include a port.
+fn parse_remote(remote: &str) -> Option<IpAddr> {
+    remote
+        .parse::<IpAddr>()
+        .ok()
+        .or_else(|| remote.parse::<SocketAddr>().ok().map(|a| a.ip()))
+        .or_else(|| {
+            remote
+                .trim_start_matches('[')
+                .trim_end_matches(']')
+                .parse()
+                .ok()
+        })
+}
+
+/// Middleware limiting requests per client IP address.
+#[derive(Clone)]
+pub struct IpRateLimit {
+    limiter: Arc<RateLimiter<IpAddr>>,
+    rate: Rate,
+    proxy_header: Option<ProxyHeader>,
+}
+
+impl IpRateLimit {
+    /// Limits each client to `rate`. With a `proxy_header`, the client
+    /// address is taken from that header, see [`client_ip`].
+    pub fn new(rate: Rate, proxy_header: Option<ProxyHeader>) -> Self {
+        Self {
+            limiter: Arc::new(RateLimiter::new()),
+            rate,
+            proxy_header,
+        }
+    }
+}
+
+impl<S, B> Transform<S> for IpRateLimit
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type InitError = ();
+    type Transform = IpRateLimitMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(IpRateLimitMiddleware {
+            service,
+            config: self.clone(),
+        })
+    }
+}
+
+pub struct IpRateLimitMiddleware<S> {
+    service: S,
+    config: IpRateLimit,
+}
+
+impl<S, B> Service for IpRateLimitMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Future = Either<S::Future, Ready<Result<Self::Response, Self::Error>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let ip = client_ip(req.headers(), req.peer_addr(), self.config.proxy_header);
+        let limited = ip.map(|ip| self.config.limiter.check(ip, &self.config.rate));
+        match limited {
+            Some(Err(wait)) => {
+                Either::Right(ok(req.into_response(too_many_requests(wait).into_body())))
+            }
+            _ => Either::Left(self.service.call(req)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_rate() {
+        let rate = "30/m".parse::<Rate>().unwrap();
+        assert_eq!(rate.requests, 30);
+        assert_eq!(rate.period, Duration::from_secs(60));
+        assert_eq!(
+            " 5 / d ".parse::<Rate>().unwrap().period,
+            Duration::from_secs(24 * 60 * 60)
+        );
+        assert_eq!(
+            "1/s".parse::<Rate>().unwrap().period,
+            Duration::from_secs(1)
+        );
+        assert_eq!(
+            "2/h".parse::<Rate>().unwrap().period,
+            Duration::from_secs(60 * 60)
+        );
+        for invalid in &["0/m", "30", "30/w", "-1/m", "x/m", ""] {
+            assert!(invalid.parse::<Rate>().is_err(), "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn bucket_refills() {
+        let limiter = RateLimiter::new();
+        let rate = "2/s".parse().unwrap();
+        let start = Instant::now();
+        assert!(limiter.check_at("a", &rate, start).is_ok());
+        assert!(limiter.check_at("a", &rate, start).is_ok());
+        let wait = limiter.check_at("a", &rate, start).unwrap_err();
+        assert_eq!(wait, Duration::from_millis(500));
+        // Other keys have their own bucket.
+        assert!(limiter.check_at("b", &rate, start).is_ok());
+        let later = start + Duration::from_millis(250);
+        let wait = limiter.check_at("a", &rate, later).unwrap_err();
+        assert_eq!(wait, Duration::from_millis(250));
+        let later = start + Duration::from_millis(500);
+        assert!(limiter.check_at("a", &rate, later).is_ok());
+        assert!(limiter.check_at("a", &rate, later).is_err());
+        // Refills stop at the capacity.
+        let later = start + Duration::from_secs(60);
+        assert!(limiter.check_at("a", &rate, later).is_ok());
+        assert!(limiter.check_at("a", &rate, later).is_ok());
+        assert!(limiter.check_at("a", &rate, later).is_err());
+    }
+
+    #[test]
+    fn prune_uses_bucket_rate() {
+        let limiter = RateLimiter::new();
+        let slow = "1/d".parse().unwrap();
+        let fast = "1000/s".parse().unwrap();
+        let start = Instant::now();
+        assert!(limiter.check_at(0, &slow, start).is_ok());
+        for key in 1..=PRUNE_THRESHOLD {
+            assert!(limiter.check_at(key, &fast, start).is_ok());
+        }
+        // Pruning at the fast rate would drop the slow bucket and let the
+        // second request through.
+        let later = start + Duration::from_secs(1);
+        assert!(limiter.check_at(PRUNE_THRESHOLD + 1, &fast, later).is_ok());
+        assert!(limiter.check_at(0, &slow, later).is_err());
+        assert!(limiter.buckets.lock().unwrap().map.len() < PRUNE_THRESHOLD);
+    }
+
+    #[test]
+    fn prune_once_per_interval() {
+        let limiter = RateLimiter::new();
+        let rate = "1/d".parse().unwrap();
+        let start = Instant::now();
+        for key in 0..=PRUNE_THRESHOLD + 1 {
+            assert!(limiter.check_at(key, &rate, start).is_ok());
+        }
+        assert!(limiter.buckets.lock().unwrap().pruned.is_some());
+        let later = start + Duration::from_secs(1);
+        assert!(limiter.check_at(0, &rate, later).is_err());
+        assert_eq!(limiter.buckets.lock().unwrap().pruned, Some(start));
+        let later = start + PRUNE_INTERVAL;
+        assert!(limiter.check_at(0, &rate, later).is_err());
+        assert_eq!(limiter.buckets.lock().unwrap().pruned, Some(later));
+    }
+
+    #[test]
+    fn limit_bucket_count() {
+        let limiter = RateLimiter::new();
+        let rate = "1/d".parse().unwrap();
+        let start = Instant::now();
+        for key in 0..MAX_BUCKETS {
+            assert!(limiter.check_at(key, &rate, start).is_ok());
+        }
+        assert_eq!(
+            limiter.check_at(MAX_BUCKETS, &rate, start),
+            Err(PRUNE_INTERVAL)
+        );
+        // Existing buckets are still checked.
+        assert!(limiter.check_at(0, &rate, start).is_err());
+    }
+
+    fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(
+                header::HeaderName::from_static(name),
+                header::HeaderValue::from_str(value).unwrap(),
+            );
+        }
+        headers
+    }
+
+    #[test]
+    fn client_ip_from_proxy_headers() {
+        let peer = Some(SocketAddr::from(([10, 0, 0, 1], 1234)));
+        let ip = |s: &str| Some(s.parse::<IpAddr>().unwrap());
+        let xff = Some(ProxyHeader::XForwardedFor);
+        let fwd = Some(ProxyHeader::Forwarded);
+
+        let spoofed = headers(&[("x-forwarded-for", "1.2.3.4, 203.0.113.7")]);
+        assert_eq!(client_ip(&spoofed, peer, None), ip("10.0.0.1"));
+        assert_eq!(client_ip(&spoofed, peer, xff), ip("203.0.113.7"));
+        assert_eq!(client_ip(&spoofed, peer, fwd), None);
+
+        // Spoofed header lines sent by the client come first.
+        let lines = headers(&[
+            ("x-forwarded-for", "1.2.3.4"),
+            ("x-forwarded-for", "203.0.113.7"),
+        ]);
+        assert_eq!(client_ip(&lines, peer, xff), ip("203.0.113.7"));
+        let lines = headers(&[
+            ("x-forwarded-for", "1.2.3.4"),
+            ("x-forwarded-for", "5.6.7.8"),
+            ("x-forwarded-for", "203.0.113.7"),
+        ]);
+        assert_eq!(client_ip(&lines, peer, xff), ip("203.0.113.7"));
+
+        // Only the header set by the proxy is used.
+        let both = headers(&[
+            ("forwarded", "for=1.2.3.4"),
+            ("x-forwarded-for", "203.0.113.7"),
+        ]);
+        assert_eq!(client_ip(&both, peer, xff), ip("203.0.113.7"));
+
+        let forwarded = headers(&[(
+            "forwarded",
+            "for=1.2.3.4, for=\"[2001:db8::1]:4711\";proto=https",
+        )]);
+        assert_eq!(client_ip(&forwarded, peer, fwd), ip("2001:db8::1"));
+        let forwarded = headers(&[("forwarded", "for=1.2.3.4;by=x, For=203.0.113.7")]);
+        assert_eq!(client_ip(&forwarded, peer, fwd), ip("203.0.113.7"));
+
+        assert_eq!(client_ip(&headers(&[]), peer, xff), None);
+        let unknown = headers(&[("forwarded", "for=unknown")]);
+        assert_eq!(client_ip(&unknown, peer, fwd), None);
+    }
+
+    #[test]
+    fn parse_proxy_header() {
+        assert_eq!(
+            "X-Forwarded-For".parse::<ProxyHeader>().unwrap(),
+            ProxyHeader::XForwardedFor
+        );
+        assert_eq!(
+            "forwarded".parse::<ProxyHeader>().unwrap(),
+            ProxyHeader::Forwarded
+        );
+        assert!("x-real-ip".parse::<ProxyHeader>().is_err());
+    }
+}
@@ -1,7 +1,41 @@
+table! {
+    answer_revisions (id) {
+        id -> Int8,
+        question_id -> Int4,
+        old_answer_used -> Nullable<Int4>,
+        new_answer_used -> Nullable<Int4>,
+        old_reviewed -> Nullable<Bool>,
+        new_reviewed -> Nullable<Bool>,
+        old_valid_answers -> Nullable<Array<Int4>>,
+        new_valid_answers -> Nullable<Array<Int4>>,
+        old_test -> Nullable<Uuid>,
+        new_test -> Nullable<Uuid>,
+        key_id -> Nullable<Uuid>,
+        source -> Text,
+        created_at -> Timestamptz,
+        old_submitted_by -> Nullable<Uuid>,
+        new_submitted_by -> Nullable<Uuid>,
+        actor_kind -> Text,
+    }
+}
+
 table! {
     answer_strings (answer_id) {
         answer_id -> Int4,
         answer_string -> Text,
+        submitted_by -> Nullable<Uuid>,
+    }
+}
+
+table! {
+    answer_proposals (id) {
+        id -> Int8,
+        question_id -> Int4,
+        answer_used -> Int4,
+        valid_answers -> Array<Int4>,
+        test -> Uuid,
+        submitted_by -> Nullable<Uuid>,
+        created_at -> Timestamptz,
     }
 }
 
@@ -12,6 +46,7 @@ table! {
         reviewed -> Bool,
         test -> Uuid,
         valid_answers -> Array<Int4>,
+        submitted_by -> Nullable<Uuid>,
     }
 }
 
@@ -19,8 +54,36 @@ table! {
     api_keys (id) {
         id -> Uuid,
         hash -> Bytea,
-        write_access -> Bool,
         note -> Nullable<Text>,
+        scopes -> Array<Text>,
+        prefix -> Nullable<Text>,
+        created_at -> Timestamptz,
+        expires_at -> Nullable<Timestamptz>,
+        last_used_at -> Nullable<Timestamptz>,
+        revoked_at -> Nullable<Timestamptz>,
+        use_count -> Int8,
+        daily_quota -> Nullable<Int4>,
+    }
+}
+
+table! {
+    api_key_usage (key_id, day) {
+        key_id -> Uuid,
+        day -> Date,
+        requests -> Int4,
+    }
+}
+
+table! {
+    audit_log (id) {
+        id -> Int8,
+        key_id -> Nullable<Uuid>,
+        endpoint -> Text,
+        created_at -> Timestamptz,
+        question_ids -> Array<Int4>,
+        answer_rows -> Int4,
+        string_rows -> Int4,
+        client_ip -> Nullable<Text>,
     }
 }
 
@@ -35,17 +98,26 @@ table! {
     question_strings (question_id) {
         question_id -> Int4,
         question_string -> Text,
+        submitted_by -> Nullable<Uuid>,
     }
 }
 
+joinable!(answer_proposals -> answers (question_id));
+joinable!(answer_proposals -> groups (test));
 joinable!(answers -> answer_strings (answer_used));
+joinable!(api_key_usage -> api_keys (key_id));
 joinable!(answers -> groups (test));
+joinable!(audit_log -> api_keys (key_id));
 joinable!(answers -> question_strings (question_id));
 
 allow_tables_to_appear_in_same_query!(
+    answer_proposals,
+    answer_revisions,
     answer_strings,
     answers,
+    api_key_usage,
     api_keys,
+    audit_log,
     groups,
     question_strings,
 );
@@ -1,19 +1,42 @@
-use failure::Error;
-use reqwest::Client;
+use failure::{Error, Fail};
+use log::{log, Level};
+pub use reqwest::Method;
+use reqwest::{Client, StatusCode};
+use serde::de::{DeserializeOwned, IgnoredAny};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use std::collections::HashMap;
+use std::path::Path;
 use std::process::Stdio;
+use std::time::{Duration, Instant};
 use tokio::io::BufReader;
 use tokio::net::TcpListener;
 use tokio::prelude::*;
 use tokio::process::{Child, Command};
 use tokio::stream::StreamExt;
+use tokio::time::delay_for;
+
+pub mod batch;
+#[cfg(feature = "bidi")]
+pub mod bidi;
+pub mod blocking;
+pub mod downloads;
+pub mod emulation;
+pub mod extract;
+pub mod forms;
+pub mod glob;
+pub mod locator;
+pub mod pool;
+#[cfg(feature = "proxy")]
+pub mod proxy;
+pub mod visual;
 
 pub struct WebDriver {
     url: String,
     client: Client,
     session: WebDriverSession,
+    dialect: Dialect,
+    retry: RetryPolicy,
     child: Option<Child>,
 }
 
@@ -23,6 +46,18 @@ impl WebDriver {
         headless: bool,
         verbose: bool,
     ) -> Result<Self, Error>
+    where
+        T: Into<String>,
+    {
+        let caps = CapabilitiesBuilder::new().headless(headless);
+        Self::new_firefox_with_caps(command, caps, verbose).await
+    }
+
+    pub async fn new_firefox_with_caps<T>(
+        command: Option<T>,
+        caps: CapabilitiesBuilder,
+        verbose: bool,
+    ) -> Result<Self, Error>
     where
         T: Into<String>,
     {
@@ -33,40 +68,34 @@ impl WebDriver {
         let port = TcpListener::bind(sa).await?.local_addr()?.port();
         let url = format!("http://127.0.0.1:{}", port);
         let mut child = Command::new(command)
-            .arg("-v")
+            .arg(if verbose { "-vv" } else { "-v" })
             .arg("-p")
             .arg(&port.to_string())
             .stdout(Stdio::piped())
-            .stderr(Stdio::null())
+            .stderr(Stdio::piped())
             .kill_on_drop(true)
             .spawn()?;
-        let mut caps = HashMap::new();
-        if headless {
-            caps.insert(
-                String::from("moz:firefoxOptions"),
-                json!({
-                    "args": ["-headless"],
-                }),
-            );
-        }
-        let mut stdout = BufReader::new(child.stdout.take().unwrap());
-        let mut lines = (&mut stdout).lines();
+        let (always_match, first_match) = caps.build();
+        let mut stderr = BufReader::new(child.stderr.take().unwrap()).lines();
+        tokio::spawn(async move {
+            while let Some(Ok(line)) = stderr.next().await {
+                log_driver_line(&line, Level::Warn);
+            }
+        });
+        let mut lines = BufReader::new(child.stdout.take().unwrap()).lines();
         loop {
             tokio::select! {
                 line = lines.next() => {
                     if let Some(line) = line {
                         let line = line?;
+                        log_driver_line(&line, Level::Info);
                         if line.contains("Listening") && line.contains(&port.to_string()) {
-                            if verbose {
-                                tokio::spawn(async move {
-                                    let _ = tokio::io::copy(&mut stdout, &mut tokio::io::stderr()).await;
-                                });
-                            } else {
-                                tokio::spawn(async move {
-                                    let _ = tokio::io::copy(&mut stdout, &mut tokio::io::sink()).await;
-                                });
-                            }
-                            let mut wd = WebDriver::new(&url, HashMap::new(), vec![caps]).await?;
+                            tokio::spawn(async move {
+                                while let Some(Ok(line)) = lines.next().await {
+                                    log_driver_line(&line, Level::Info);
+                                }
+                            });
+                            let mut wd = WebDriver::new(&url, always_match, first_match).await?;
                             wd.child = Some(child);
                             return Ok(wd);
                         }
@@ -79,60 +108,132 @@ impl WebDriver {
         }
     }
 
+    /// Creates a session on the remote end at `url`.
+    ///
+    /// W3C capabilities are sent first. If the remote end rejects them, the
+    /// session is requested again with legacy JSON Wire Protocol
+    /// capabilities. The [`Dialect`] is detected from the response.
     pub async fn new<T: Into<String> + std::fmt::Display>(
         url: T,
         always_match: HashMap<String, serde_json::Value>,
         first_match: Vec<HashMap<String, serde_json::Value>>,
     ) -> Result<Self, Error> {
-        let req = NewSessionRequest {
+        let mut desired_capabilities = always_match.clone();
+        if let Some(first) = first_match.first() {
+            desired_capabilities.extend(first.clone());
+        }
+        let client = Client::new();
+        let retry = RetryPolicy::default();
+        let new_url = format!("{}/session", url);
+        let req = NewSessionRequest::W3C {
             capabilities: Capabilities {
                 always_match,
                 first_match,
             },
         };
-        let client = Client::new();
-        let new_url = format!("{}/session", url);
-        let session: WdResponse<_> = client
-            .post(&new_url)
-            .json(&req)
-            .send()
-            .await?
-            .error_for_status()?
-            .json()
-            .await?;
+        let body = serde_json::to_value(&req)?;
+        let resp =
+            match request_raw(&client, &retry, Method::POST, &new_url, Some(body), false).await {
+                Err(e) if e.downcast_ref::<WebDriverError>().is_some() => {
+                    let req = NewSessionRequest::Legacy {
+                        desired_capabilities,
+                    };
+                    let body = serde_json::to_value(&req)?;
+                    // Report the W3C error if the legacy request fails too.
+                    request_raw(&client, &retry, Method::POST, &new_url, Some(body), false)
+                        .await
+                        .map_err(|_| e)?
+                }
+                resp => resp?,
+            };
+        let (session, dialect) = resp.into_session()?;
         Ok(Self {
             url: url.into(),
-            session: session.value,
+            session,
+            dialect,
             client,
+            retry,
             child: None,
         })
     }
 
+    /// Returns the BiDi WebSocket URL if one was negotiated for the session.
+    pub fn web_socket_url(&self) -> Option<&str> {
+        self.session
+            .capabilities
+            .get("webSocketUrl")
+            .and_then(|url| url.as_str())
+    }
+
+    /// Returns the protocol dialect spoken by the remote end.
+    pub fn dialect(&self) -> Dialect {
+        self.dialect
+    }
+
+    /// Returns the retry policy used for commands.
+    pub fn retry_policy(&self) -> &RetryPolicy {
+        &self.retry
+    }
+
+    /// Sets the retry policy used for commands.
+    pub fn set_retry_policy(&mut self, retry: RetryPolicy) {
+        self.retry = retry;
+    }
+
+    /// Sends a command to the current session.
+    ///
+    /// `path` is relative to the session URL, e.g. `element/{id}/click`.
+    /// Commands without a body are sent as an empty JSON object for
+    /// `POST` requests. This can be used for vendor commands that have
+    /// no dedicated method. Script commands are mapped to their legacy
+    /// paths for [`Dialect::Legacy`] sessions. Only `GET` commands are
+    /// retried after the request may have reached the remote end.
+    pub async fn send_command<T: DeserializeOwned>(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<serde_json::Value>,
+    ) -> Result<T, Error> {
+        self.command(method, path, body, false).await
+    }
+
+    async fn command<T: DeserializeOwned>(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<serde_json::Value>,
+        lookup: bool,
+    ) -> Result<T, Error> {
+        let path = match (self.dialect, path) {
+            (Dialect::Legacy, "execute/sync") => "execute",
+            (Dialect::Legacy, "execute/async") => "execute_async",
+            (_, path) => path,
+        };
+        let url = format!(
+            "{base}/session/{session}/{path}",
+            base = self.url,
+            session = self.session.session_id,
+            path = path
+        );
+        request(&self.client, &self.retry, method, &url, body, lookup).await
+    }
+
     pub async fn get_elements<T: Into<String>>(
         &self,
         using: Using,
         value: T,
     ) -> Result<Vec<WebElement>, Error> {
-        let session_id = &self.session.session_id;
-        let url = format!(
-            "{base}/session/{session}/elements",
-            base = self.url,
-            session = session_id
-        );
         let req = ElementRequest {
             using,
             value: value.into(),
         };
-        Ok(self
-            .client
-            .post(&url)
-            .json(&req)
-            .send()
-            .await?
-            .error_for_status()?
-            .json::<WdResponse<_>>()
-            .await?
-            .value)
+        self.command(
+            Method::POST,
+            "elements",
+            Some(serde_json::to_value(&req)?),
+            true,
+        )
+        .await
     }
 
     pub async fn get_element<T: Into<String>>(
@@ -140,26 +241,17 @@ impl WebDriver {
         using: Using,
         value: T,
     ) -> Result<WebElement, Error> {
-        let session_id = &self.session.session_id;
-        let url = format!(
-            "{base}/session/{session}/element",
-            base = self.url,
-            session = session_id
-        );
         let req = ElementRequest {
             using,
             value: value.into(),
         };
-        Ok(self
-            .client
-            .post(&url)
-            .json(&req)
-            .send()
-            .await?
-            .error_for_status()?
-            .json::<WdResponse<_>>()
-            .await?
-            .value)
+        self.command(
+            Method::POST,
+            "element",
+            Some(serde_json::to_value(&req)?),
+            true,
+        )
+        .await
     }
 
     pub async fn get_elements_from_element<T: Into<String>>(
@@ -168,27 +260,13 @@ impl WebDriver {
         using: Using,
         value: T,
     ) -> Result<Vec<WebElement>, Error> {
-        let session_id = &self.session.session_id;
-        let url = format!(
-            "{base}/session/{session}/element/{element}/elements",
-            base = self.url,
-            session = session_id,
-            element = element.element_id,
-        );
+        let path = format!("element/{}/elements", element.element_id);
         let req = ElementRequest {
             using,
             value: value.into(),
         };
-        Ok(self
-            .client
-            .post(&url)
-            .json(&req)
-            .send()
-            .await?
-            .error_for_status()?
-            .json::<WdResponse<_>>()
-            .await?
-            .value)
+        self.command(Method::POST, &path, Some(serde_json::to_value(&req)?), true)
+            .await
     }
 
     pub async fn get_element_from_element<T: Into<String>>(
@@ -197,42 +275,19 @@ impl WebDriver {
         using: Using,
         value: T,
     ) -> Result<WebElement, Error> {
-        let session_id = &self.session.session_id;
-        let url = format!(
-            "{base}/session/{session}/element/{element}/element",
-            base = self.url,
-            session = session_id,
-            element = element.element_id,
-        );
+        let path = format!("element/{}/element", element.element_id);
         let req = ElementRequest {
             using,
             value: value.into(),
         };
-        Ok(self
-            .client
-            .post(&url)
-            .json(&req)
-            .send()
-            .await?
-            .error_for_status()?
-            .json::<WdResponse<_>>()
-            .await?
-            .value)
+        self.command(Method::POST, &path, Some(serde_json::to_value(&req)?), true)
+            .await
     }
 
     pub async fn element_click(&self, element: &WebElement) -> Result<(), Error> {
-        let url = format!(
-            "{base}/session/{session}/element/{element}/click",
-            base = self.url,
-            session = self.session.session_id,
-            element = element.element_id
-        );
-        self.client
-            .post(&url)
-            .json(&json!({}))
-            .send()
-            .await?
-            .error_for_status()?;
+        let path = format!("element/{}/click", element.element_id);
+        self.send_command::<IgnoredAny>(Method::POST, &path, None)
+            .await?;
         Ok(())
     }
 
@@ -241,63 +296,29 @@ impl WebDriver {
         element: &WebElement,
         attr: &str,
     ) -> Result<String, Error> {
-        let url = format!(
-            "{base}/session/{session}/element/{element}/attribute/{name}",
-            base = self.url,
-            session = self.session.session_id,
-            element = element.element_id,
-            name = attr
-        );
-        Ok(self
-            .client
-            .get(&url)
-            .send()
-            .await?
-            .error_for_status()?
-            .json::<WdResponse<_>>()
-            .await?
-            .value)
-    }
-
-    pub async fn get_element_prop<T: serde::de::DeserializeOwned>(
+        let path = format!("element/{}/attribute/{}", element.element_id, attr);
+        self.send_command(Method::GET, &path, None).await
+    }
+
+    pub async fn get_element_prop<T: DeserializeOwned>(
         &self,
         element: &WebElement,
         prop: &str,
     ) -> Result<T, Error> {
-        let url = format!(
-            "{base}/session/{session}/element/{element}/property/{name}",
-            base = self.url,
-            session = self.session.session_id,
-            element = element.element_id,
-            name = prop
-        );
-        Ok(self
-            .client
-            .get(&url)
-            .send()
-            .await?
-            .error_for_status()?
-            .json::<WdResponse<_>>()
-            .await?
-            .value)
+        if self.dialect == Dialect::Legacy {
+            // The JSON Wire Protocol has no property command.
+            let args = vec![serde_json::to_value(element)?, json!(prop)];
+            return self
+                .run_script("return arguments[0][arguments[1]];", args)
+                .await;
+        }
+        let path = format!("element/{}/property/{}", element.element_id, prop);
+        self.send_command(Method::GET, &path, None).await
     }
 
     pub async fn get_element_text(&self, element: &WebElement) -> Result<String, Error> {
-        let url = format!(
-            "{base}/session/{session}/element/{element}/text",
-            base = self.url,
-            session = self.session.session_id,
-            element = element.element_id
-        );
-        Ok(self
-            .client
-            .get(&url)
-            .send()
-            .await?
-            .error_for_status()?
-            .json::<WdResponse<_>>()
-            .await?
-            .value)
+        let path = format!("element/{}/text", element.element_id);
+        self.send_command(Method::GET, &path, None).await
     }
 
     pub async fn element_send_keys<T: Into<String>>(
@@ -305,19 +326,16 @@ impl WebDriver {
         element: &WebElement,
         keys: T,
     ) -> Result<(), Error> {
-        let url = format!(
-            "{base}/session/{session}/element/{element}/value",
-            base = self.url,
-            session = self.session.session_id,
-            element = element.element_id
-        );
-        let req = SendKeyRequest { text: keys.into() };
-        self.client
-            .post(&url)
-            .json(&req)
-            .send()
-            .await?
-            .error_for_status()?;
+        let path = format!("element/{}/value", element.element_id);
+        let keys = keys.into();
+        let req = match self.dialect {
+            Dialect::W3C => SendKeyRequest::W3C { text: keys },
+            Dialect::Legacy => SendKeyRequest::Legacy {
+                value: keys.chars().map(String::from).collect(),
+            },
+        };
+        self.send_command::<IgnoredAny>(Method::POST, &path, Some(serde_json::to_value(&req)?))
+            .await?;
         Ok(())
     }
 
@@ -325,63 +343,197 @@ impl WebDriver {
     where
         T: Into<String> + Serialize,
     {
-        let url = format!(
-            "{base}/session/{session}/execute/sync",
-            base = self.url,
-            session = self.session.session_id
-        );
         let data = json!({
             "args": [],
             "script": script,
         });
-        self.client
-            .post(&url)
-            .json(&data)
-            .send()
-            .await?
-            .error_for_status()?;
+        self.send_command::<IgnoredAny>(Method::POST, "execute/sync", Some(data))
+            .await?;
         Ok(())
     }
 
     pub async fn run_script_elem<T, V>(&self, script: T, element: &WebElement) -> Result<V, Error>
     where
         T: Into<String>,
-        V: serde::de::DeserializeOwned,
+        V: DeserializeOwned,
     {
-        let url = format!(
-            "{base}/session/{session}/execute/sync",
-            base = self.url,
-            session = self.session.session_id
-        );
         let req = ScriptInvokeElem {
             args: [element.clone()],
             script: script.into(),
         };
-        Ok(self
-            .client
-            .post(&url)
-            .json(&req)
-            .send()
-            .await?
-            .error_for_status()?
-            .json::<WdResponse<_>>()
-            .await?
-            .value)
+        self.send_command(
+            Method::POST,
+            "execute/sync",
+            Some(serde_json::to_value(&req)?),
+        )
+        .await
+    }
+
+    /// Runs a synchronous script with `args` and returns its result.
+    pub async fn run_script<T, V>(
+        &self,
+        script: T,
+        args: Vec<serde_json::Value>,
+    ) -> Result<V, Error>
+    where
+        T: Into<String>,
+        V: DeserializeOwned,
+    {
+        let req = ScriptInvoke {
+            script: script.into(),
+            args,
+        };
+        self.send_command(
+            Method::POST,
+            "execute/sync",
+            Some(serde_json::to_value(&req)?),
+        )
+        .await
+    }
+
+    pub async fn get_window_rect(&self) -> Result<WindowRect, Error> {
+        if self.dialect == Dialect::Legacy {
+            let position: serde_json::Value = self
+                .send_command(Method::GET, "window/current/position", None)
+                .await?;
+            let size: serde_json::Value = self
+                .send_command(Method::GET, "window/current/size", None)
+                .await?;
+            return Ok(WindowRect {
+                x: serde_json::from_value(position["x"].clone())?,
+                y: serde_json::from_value(position["y"].clone())?,
+                width: serde_json::from_value(size["width"].clone())?,
+                height: serde_json::from_value(size["height"].clone())?,
+            });
+        }
+        self.send_command(Method::GET, "window/rect", None).await
+    }
+
+    /// Moves and resizes the window. Fields left as `None` are unchanged.
+    pub async fn set_window_rect(
+        &self,
+        x: Option<i32>,
+        y: Option<i32>,
+        width: Option<u32>,
+        height: Option<u32>,
+    ) -> Result<WindowRect, Error> {
+        if self.dialect == Dialect::Legacy {
+            // Legacy position and size commands need both coordinates.
+            let current = self.get_window_rect().await?;
+            if x.is_some() || y.is_some() {
+                let req = json!({
+                    "x": x.unwrap_or(current.x),
+                    "y": y.unwrap_or(current.y),
+                });
+                self.send_command::<IgnoredAny>(Method::POST, "window/current/position", Some(req))
+                    .await?;
+            }
+            if width.is_some() || height.is_some() {
+                let req = json!({
+                    "width": width.unwrap_or(current.width),
+                    "height": height.unwrap_or(current.height),
+                });
+                self.send_command::<IgnoredAny>(Method::POST, "window/current/size", Some(req))
+                    .await?;
+            }
+            return self.get_window_rect().await;
+        }
+        let req = json!({
+            "x": x,
+            "y": y,
+            "width": width,
+            "height": height,
+        });
+        self.send_command(Method::POST, "window/rect", Some(req))
+            .await
     }
 
     pub async fn navigate<T: Into<String>>(&self, url: T) -> Result<(), Error> {
-        let requrl = format!(
-            "{base}/session/{session}/url",
-            base = self.url,
-            session = self.session.session_id
-        );
         let req = NavigateRequest { url: url.into() };
-        self.client
-            .post(&requrl)
-            .json(&req)
-            .send()
-            .await?
-            .error_for_status()?;
+        self.send_command::<IgnoredAny>(Method::POST, "url", Some(serde_json::to_value(&req)?))
+            .await?;
+        Ok(())
+    }
+
+    pub async fn get_title(&self) -> Result<String, Error> {
+        self.send_command(Method::GET, "title", None).await
+    }
+
+    pub async fn get_current_url(&self) -> Result<String, Error> {
+        self.send_command(Method::GET, "url", None).await
+    }
+
+    /// Takes a screenshot of the current page and returns it as PNG data.
+    pub async fn screenshot(&self) -> Result<Vec<u8>, Error> {
+        let data: String = self.send_command(Method::GET, "screenshot", None).await?;
+        Ok(base64::decode(&data)?)
+    }
+
+    /// Takes a screenshot of an element and returns it as PNG data.
+    ///
+    /// Legacy remote ends have no element screenshot command, so the
+    /// element is scrolled into view and cropped from a page screenshot.
+    pub async fn element_screenshot(&self, element: &WebElement) -> Result<Vec<u8>, Error> {
+        if self.dialect == Dialect::Legacy {
+            let path = format!("element/{}/location_in_view", element.element_id);
+            let location: serde_json::Value = self.send_command(Method::GET, &path, None).await?;
+            let path = format!("element/{}/size", element.element_id);
+            let size: serde_json::Value = self.send_command(Method::GET, &path, None).await?;
+            let coord = |v: &serde_json::Value| v.as_f64().unwrap_or(0.0).max(0.0) as u32;
+            let png = self.screenshot().await?;
+            return visual::crop(
+                &png,
+                coord(&location["x"]),
+                coord(&location["y"]),
+                coord(&size["width"]),
+                coord(&size["height"]),
+            );
+        }
+        let path = format!("element/{}/screenshot", element.element_id);
+        let data: String = self.send_command(Method::GET, &path, None).await?;
+        Ok(base64::decode(&data)?)
+    }
+
+    /// Polls for an element until it is found or `timeout` has elapsed.
+    pub async fn wait_for_element<T: Into<String>>(
+        &self,
+        using: Using,
+        value: T,
+        timeout: Duration,
+    ) -> Result<WebElement, Error> {
+        let value = value.into();
+        let deadline = Instant::now() + timeout;
+        loop {
+            match self.get_element(using, value.clone()).await {
+                Err(e) if is_no_such_element(&e) && Instant::now() < deadline => {
+                    delay_for(WAIT_INTERVAL).await;
+                }
+                ret => return ret,
+            }
+        }
+    }
+
+    /// Polls until at least one element matches or `timeout` has elapsed.
+    pub async fn wait_for_elements<T: Into<String>>(
+        &self,
+        using: Using,
+        value: T,
+        timeout: Duration,
+    ) -> Result<Vec<WebElement>, Error> {
+        let value = value.into();
+        let deadline = Instant::now() + timeout;
+        loop {
+            let elements = self.get_elements(using, value.clone()).await?;
+            if !elements.is_empty() || Instant::now() >= deadline {
+                return Ok(elements);
+            }
+            delay_for(WAIT_INTERVAL).await;
+        }
+    }
+
+    pub async fn delete_all_cookies(&self) -> Result<(), Error> {
+        self.send_command::<IgnoredAny>(Method::DELETE, "cookie", None)
+            .await?;
         Ok(())
     }
 
@@ -391,12 +543,451 @@ impl WebDriver {
             base = self.url,
             session = self.session.session_id
         );
-        self.client.delete(&url).send().await?.error_for_status()?;
+        request::<IgnoredAny>(&self.client, &self.retry, Method::DELETE, &url, None, false).await?;
         Ok(())
     }
 }
 
-#[derive(Serialize, Deserialize)]
+/// Emits a line of geckodriver output through the `log` crate.
+///
+/// Lines in geckodriver's `timestamp\tmodule\tLEVEL\tmessage` format are
+/// logged under the `webdriver::geckodriver` target, with modules other
+/// than geckodriver itself (e.g. Marionette) as a sub-target. Other lines,
+/// such as raw browser output, are logged at `default` level.
+fn log_driver_line(line: &str, default: Level) {
+    let (target, level, msg) = driver_log_record(line, default);
+    log!(target: &target, level, "{}", msg);
+}
+
+/// Returns the target, level and message [`log_driver_line`] uses for
+/// `line`.
+fn driver_log_record(line: &str, default: Level) -> (String, Level, &str) {
+    const TARGET: &str = "webdriver::geckodriver";
+    let mut parts = line.splitn(4, '\t');
+    let parsed = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(ts), Some(module), Some(level), Some(msg)) if ts.parse::<u64>().is_ok() => {
+            let level = match level {
+                "FATAL" | "ERROR" => Some(Level::Error),
+                "WARN" => Some(Level::Warn),
+                "INFO" | "CONFIG" => Some(Level::Info),
+                "DEBUG" => Some(Level::Debug),
+                "TRACE" => Some(Level::Trace),
+                _ => None,
+            };
+            level.map(|level| (module, level, msg))
+        }
+        _ => None,
+    };
+    match parsed {
+        Some(("geckodriver", level, msg)) => (TARGET.to_owned(), level, msg),
+        Some((module, level, msg)) => {
+            let target = format!("{}::{}", TARGET, module.to_lowercase());
+            (target, level, msg)
+        }
+        None => (TARGET.to_owned(), default, line),
+    }
+}
+
+/// Delay between attempts when waiting for elements.
+const WAIT_INTERVAL: Duration = Duration::from_millis(250);
+
+/// MIME types Firefox saves without asking when a download directory is set.
+const DOWNLOAD_MIME_TYPES: &[&str] = &[
+    "application/octet-stream",
+    "application/pdf",
+    "application/zip",
+    "application/json",
+    "application/vnd.ms-excel",
+    "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+    "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+    "text/csv",
+    "text/plain",
+];
+
+/// Builder for the capabilities requested at session creation.
+#[derive(Clone, Default)]
+pub struct CapabilitiesBuilder {
+    always_match: HashMap<String, serde_json::Value>,
+    first_match: Vec<HashMap<String, serde_json::Value>>,
+    firefox_args: Vec<String>,
+    firefox_prefs: serde_json::Map<String, serde_json::Value>,
+    chrome_args: Vec<String>,
+    chrome_prefs: serde_json::Map<String, serde_json::Value>,
+}
+
+impl CapabilitiesBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Runs the browser without a visible window.
+    pub fn headless(mut self, headless: bool) -> Self {
+        self.firefox_args.retain(|arg| arg != "-headless");
+        if headless {
+            self.firefox_args.push(String::from("-headless"));
+        }
+        self
+    }
+
+    /// Requests a WebDriver BiDi WebSocket URL for the session.
+    pub fn web_socket_url(self, enable: bool) -> Self {
+        self.capability("webSocketUrl", enable)
+    }
+
+    /// Sets an arbitrary capability that must always match.
+    pub fn capability<T, V>(mut self, name: T, value: V) -> Self
+    where
+        T: Into<String>,
+        V: Into<serde_json::Value>,
+    {
+        self.always_match.insert(name.into(), value.into());
+        self
+    }
+
+    /// Sets a Firefox preference for the session profile.
+    pub fn firefox_pref<T, V>(mut self, name: T, value: V) -> Self
+    where
+        T: Into<String>,
+        V: Into<serde_json::Value>,
+    {
+        self.firefox_prefs.insert(name.into(), value.into());
+        self
+    }
+
+    /// Adds a Chrome command line argument.
+    pub fn chrome_arg<T: Into<String>>(mut self, arg: T) -> Self {
+        self.chrome_args.push(arg.into());
+        self
+    }
+
+    /// Sets a Chrome user preference.
+    pub fn chrome_pref<T, V>(mut self, name: T, value: V) -> Self
+    where
+        T: Into<String>,
+        V: Into<serde_json::Value>,
+    {
+        self.chrome_prefs.insert(name.into(), value.into());
+        self
+    }
+
+    /// Saves downloads to `dir` without prompting.
+    pub fn download_dir<P: AsRef<Path>>(self, dir: P) -> Self {
+        let dir = dir.as_ref().to_string_lossy().into_owned();
+        self.firefox_pref("browser.download.folderList", 2)
+            .firefox_pref("browser.download.dir", dir.clone())
+            .firefox_pref("browser.download.useDownloadDir", true)
+            .firefox_pref("browser.download.manager.showWhenStarting", false)
+            .firefox_pref("pdfjs.disabled", true)
+            .firefox_pref(
+                "browser.helperApps.neverAsk.saveToDisk",
+                DOWNLOAD_MIME_TYPES.join(","),
+            )
+            .chrome_pref("download.default_directory", dir)
+            .chrome_pref("download.prompt_for_download", false)
+            .chrome_pref("download.directory_upgrade", true)
+            .chrome_pref("plugins.always_open_pdf_externally", true)
+    }
+
+    /// Overrides the browser's user agent string.
+    pub fn user_agent<T: Into<String>>(self, user_agent: T) -> Self {
+        let user_agent = user_agent.into();
+        self.chrome_arg(format!("--user-agent={}", user_agent))
+            .firefox_pref("general.useragent.override", user_agent)
+    }
+
+    /// Accepts invalid and self-signed TLS certificates.
+    pub fn accept_insecure_certs(self, accept: bool) -> Self {
+        self.capability("acceptInsecureCerts", accept)
+    }
+
+    /// Sends HTTP and HTTPS traffic through the proxy at `host_port`,
+    /// including requests to localhost.
+    pub fn proxy<T: Into<String>>(self, host_port: T) -> Self {
+        let host_port = host_port.into();
+        self.capability(
+            "proxy",
+            json!({
+                "proxyType": "manual",
+                "httpProxy": host_port,
+                "sslProxy": host_port,
+            }),
+        )
+        .firefox_pref("network.proxy.allow_hijacking_localhost", true)
+        .chrome_arg("--proxy-bypass-list=<-loopback>")
+    }
+
+    /// Adds a set of capabilities of which the first matching one is used.
+    pub fn first_match(mut self, caps: HashMap<String, serde_json::Value>) -> Self {
+        self.first_match.push(caps);
+        self
+    }
+
+    pub fn build(
+        self,
+    ) -> (
+        HashMap<String, serde_json::Value>,
+        Vec<HashMap<String, serde_json::Value>>,
+    ) {
+        let mut always_match = self.always_match;
+        merge_browser_options(
+            &mut always_match,
+            "moz:firefoxOptions",
+            self.firefox_args,
+            self.firefox_prefs,
+        );
+        merge_browser_options(
+            &mut always_match,
+            "goog:chromeOptions",
+            self.chrome_args,
+            self.chrome_prefs,
+        );
+        (always_match, self.first_match)
+    }
+}
+
+/// Adds `args` and `prefs` to the browser options capability `name`,
+/// keeping any options set with [`CapabilitiesBuilder::capability`].
+fn merge_browser_options(
+    caps: &mut HashMap<String, serde_json::Value>,
+    name: &str,
+    args: Vec<String>,
+    prefs: serde_json::Map<String, serde_json::Value>,
+) {
+    if args.is_empty() && prefs.is_empty() {
+        return;
+    }
+    let options = caps.entry(name.to_owned()).or_insert_with(|| json!({}));
+    if !options.is_object() {
+        *options = json!({});
+    }
+    let options = options.as_object_mut().unwrap();
+    let existing_args = options.entry("args").or_insert_with(|| json!([]));
+    match existing_args.as_array_mut() {
+        Some(existing) => existing.extend(args.into_iter().map(serde_json::Value::from)),
+        None => *existing_args = json!(args),
+    }
+    let existing_prefs = options.entry("prefs").or_insert_with(|| json!({}));
+    match existing_prefs.as_object_mut() {
+        Some(existing) => existing.extend(prefs),
+        None => *existing_prefs = serde_json::Value::Object(prefs),
+    }
+}
+
+/// Wire protocol spoken by the remote end.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Dialect {
+    /// W3C WebDriver
+    W3C,
+    /// Selenium JSON Wire Protocol, with `status` response envelopes
+    Legacy,
+}
+
+/// Policy for retrying commands that failed for transient reasons.
+///
+/// Refused connections are retried for every command since the request
+/// never reached the remote end. Timeouts and dropped connections are
+/// only retried for commands that are safe to repeat, such as `GET`
+/// commands and element lookups. Element lookups are also retried when
+/// the driver reports a stale element reference, which happens while the
+/// page is re-rendering.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    /// Maximum number of retries after the first attempt
+    pub max_retries: u32,
+    /// Delay before the first retry
+    pub initial_backoff: Duration,
+    /// Upper bound for the delay, which doubles after every retry
+    pub max_backoff: Duration,
+}
+
+impl RetryPolicy {
+    /// A policy that never retries.
+    pub fn none() -> Self {
+        Self {
+            max_retries: 0,
+            ..Self::default()
+        }
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(2),
+        }
+    }
+}
+
+/// Error returned by the remote end for a failed command.
+#[derive(Debug, Fail)]
+#[fail(display = "webdriver error {} ({}): {}", error, status, message)]
+pub struct WebDriverError {
+    /// HTTP status of the response
+    pub status: u16,
+    /// WebDriver error code, e.g. `no such element`
+    pub error: String,
+    /// Human readable error message
+    pub message: String,
+}
+
+async fn request<T: DeserializeOwned>(
+    client: &Client,
+    retry: &RetryPolicy,
+    method: Method,
+    url: &str,
+    body: Option<serde_json::Value>,
+    lookup: bool,
+) -> Result<T, Error> {
+    let resp = request_raw(client, retry, method, url, body, lookup).await?;
+    Ok(serde_json::from_value(resp.value)?)
+}
+
+async fn request_raw(
+    client: &Client,
+    retry: &RetryPolicy,
+    method: Method,
+    url: &str,
+    body: Option<serde_json::Value>,
+    lookup: bool,
+) -> Result<WdResponse, Error> {
+    let idempotent = lookup || method == Method::GET;
+    let mut attempt = 0;
+    let mut backoff = retry.initial_backoff;
+    loop {
+        match request_once(client, method.clone(), url, body.as_ref()).await {
+            Err(e) if attempt < retry.max_retries && is_retryable(&e, lookup, idempotent) => {
+                delay_for(backoff).await;
+                backoff = std::cmp::min(backoff * 2, retry.max_backoff);
+                attempt += 1;
+            }
+            ret => return ret,
+        }
+    }
+}
+
+async fn request_once(
+    client: &Client,
+    method: Method,
+    url: &str,
+    body: Option<&serde_json::Value>,
+) -> Result<WdResponse, Error> {
+    let mut req = client.request(method.clone(), url);
+    if let Some(body) = body {
+        req = req.json(body);
+    } else if method == Method::POST {
+        req = req.json(&json!({}));
+    }
+    let resp = req.send().await?;
+    let status = resp.status();
+    let bytes = resp.bytes().await?;
+    parse_response(status, &bytes)
+}
+
+/// Parses a command response, turning W3C and legacy errors into
+/// [`WebDriverError`]s.
+fn parse_response(status: StatusCode, bytes: &[u8]) -> Result<WdResponse, Error> {
+    let parsed = serde_json::from_slice::<WdResponse>(bytes);
+    // Legacy remote ends may report errors with a successful HTTP status.
+    if let Ok(WdResponse {
+        status: Some(code),
+        value,
+        ..
+    }) = &parsed
+    {
+        if *code != 0 {
+            let value =
+                serde_json::from_value::<LegacyErrorValue>(value.clone()).unwrap_or_default();
+            return Err(WebDriverError {
+                status: status.as_u16(),
+                error: legacy_error_code(*code).to_owned(),
+                message: value.message,
+            }
+            .into());
+        }
+    }
+    if !status.is_success() {
+        return match parsed
+            .ok()
+            .and_then(|err| serde_json::from_value::<WdErrorValue>(err.value).ok())
+        {
+            Some(err) => Err(WebDriverError {
+                status: status.as_u16(),
+                error: err.error,
+                message: err.message,
+            }
+            .into()),
+            None => Err(failure::format_err!(
+                "webdriver request failed with status {}",
+                status
+            )),
+        };
+    }
+    Ok(parsed?)
+}
+
+/// Maps a JSON Wire Protocol status code to the W3C error code.
+fn legacy_error_code(status: i64) -> &'static str {
+    match status {
+        6 => "invalid session id",
+        7 => "no such element",
+        8 => "no such frame",
+        9 => "unknown command",
+        10 => "stale element reference",
+        11 => "element not interactable",
+        12 | 15 => "invalid element state",
+        17 => "javascript error",
+        19 | 32 => "invalid selector",
+        21 => "timeout",
+        23 => "no such window",
+        24 => "invalid cookie domain",
+        25 => "unable to set cookie",
+        26 => "unexpected alert open",
+        27 => "no such alert",
+        28 => "script timeout",
+        29 => "invalid argument",
+        33 => "session not created",
+        34 => "move target out of bounds",
+        _ => "unknown error",
+    }
+}
+
+fn is_no_such_element(error: &Error) -> bool {
+    match error.downcast_ref::<WebDriverError>() {
+        Some(e) => e.error == "no such element",
+        None => false,
+    }
+}
+
+/// Whether a failed request can be sent again. Requests that may have
+/// been received are only retried if they are `idempotent`, and stale
+/// element references only for element lookups.
+fn is_retryable(error: &Error, lookup: bool, idempotent: bool) -> bool {
+    if let Some(e) = error.downcast_ref::<WebDriverError>() {
+        return lookup && e.error == "stale element reference";
+    }
+    if let Some(e) = error.downcast_ref::<reqwest::Error>() {
+        if e.is_timeout() {
+            return idempotent;
+        }
+        let mut source = std::error::Error::source(e);
+        while let Some(err) = source {
+            if let Some(io) = err.downcast_ref::<std::io::Error>() {
+                use std::io::ErrorKind::*;
+                match io.kind() {
+                    ConnectionRefused => return true,
+                    ConnectionReset | ConnectionAborted | BrokenPipe => return idempotent,
+                    _ => {}
+                }
+            }
+            source = err.source();
+        }
+    }
+    false
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Using {
     #[serde(rename = "css selector")]
     CssSelector,
@@ -416,8 +1007,15 @@ struct NavigateRequest {
 }
 
 #[derive(Serialize, Deserialize)]
-struct SendKeyRequest {
-    text: String,
+#[serde(untagged)]
+enum SendKeyRequest {
+    W3C {
+        text: String,
+    },
+    /// Legacy remote ends expect the keys as an array of characters
+    Legacy {
+        value: Vec<String>,
+    },
 }
 
 #[derive(Serialize, Deserialize)]
@@ -426,18 +1024,67 @@ struct ElementRequest {
     value: String,
 }
 
+#[derive(Serialize, Deserialize)]
+struct ScriptInvoke {
+    script: String,
+    args: Vec<serde_json::Value>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WindowRect {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
 #[derive(Serialize, Deserialize)]
 struct ScriptInvokeElem {
     script: String,
     args: [WebElement; 1],
 }
 
+/// A reference to an element in the current page.
+///
+/// Both the W3C and the legacy `ELEMENT` key are accepted when
+/// deserializing, and both are written when serializing.
 #[derive(Serialize, Deserialize, Clone)]
+#[serde(try_from = "ElementReference", into = "ElementReference")]
 pub struct WebElement {
-    #[serde(rename = "element-6066-11e4-a52e-4f735466cecf")]
     element_id: String,
 }
 
+#[derive(Serialize, Deserialize)]
+struct ElementReference {
+    #[serde(
+        rename = "element-6066-11e4-a52e-4f735466cecf",
+        skip_serializing_if = "Option::is_none"
+    )]
+    w3c: Option<String>,
+    #[serde(rename = "ELEMENT", skip_serializing_if = "Option::is_none")]
+    legacy: Option<String>,
+}
+
+impl std::convert::TryFrom<ElementReference> for WebElement {
+    type Error = &'static str;
+
+    fn try_from(reference: ElementReference) -> Result<Self, Self::Error> {
+        match reference.w3c.or(reference.legacy) {
+            Some(element_id) => Ok(Self { element_id }),
+            None => Err("missing element reference"),
+        }
+    }
+}
+
+impl From<WebElement> for ElementReference {
+    fn from(element: WebElement) -> Self {
+        Self {
+            w3c: Some(element.element_id.clone()),
+            legacy: Some(element.element_id),
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
 struct WebDriverSession {
@@ -445,9 +1092,46 @@ struct WebDriverSession {
     capabilities: HashMap<String, serde_json::Value>,
 }
 
+/// Response envelope, with the fields only sent by legacy remote ends.
 #[derive(Serialize, Deserialize)]
-struct WdResponse<T> {
-    value: T,
+#[serde(rename_all = "camelCase")]
+struct WdResponse {
+    #[serde(default)]
+    value: serde_json::Value,
+    #[serde(default)]
+    status: Option<i64>,
+    #[serde(default)]
+    session_id: Option<String>,
+}
+
+impl WdResponse {
+    /// Reads the session created by a new session command.
+    fn into_session(self) -> Result<(WebDriverSession, Dialect), Error> {
+        // Legacy remote ends return the session id next to the value.
+        Ok(match self.session_id {
+            Some(session_id) => {
+                let session = WebDriverSession {
+                    session_id,
+                    capabilities: serde_json::from_value(self.value)?,
+                };
+                (session, Dialect::Legacy)
+            }
+            None => (serde_json::from_value(self.value)?, Dialect::W3C),
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct WdErrorValue {
+    error: String,
+    #[serde(default)]
+    message: String,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct LegacyErrorValue {
+    #[serde(default)]
+    message: String,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -457,8 +1141,194 @@ struct Capabilities {
     first_match: Vec<HashMap<String, serde_json::Value>>,
 }
 
-#[derive(Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
-struct NewSessionRequest {
-    capabilities: Capabilities,
+#[derive(Serialize)]
+#[serde(untagged)]
+enum NewSessionRequest {
+    W3C {
+        capabilities: Capabilities,
+    },
+    #[serde(rename_all = "camelCase")]
+    Legacy {
+        desired_capabilities: HashMap<String, serde_json::Value>,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wd_error(error: &str) -> Error {
+        WebDriverError {
+            status: 404,
+            error: error.to_owned(),
+            message: String::new(),
+        }
+        .into()
+    }
+
+    #[test]
+    fn retry_stale_lookups() {
+        let stale = wd_error("stale element reference");
+        assert!(is_retryable(&stale, true, true));
+        assert!(!is_retryable(&stale, false, true));
+        assert!(!is_retryable(&stale, false, false));
+        assert!(!is_retryable(&wd_error("no such element"), true, true));
+    }
+
+    fn wd_error_of(result: Result<WdResponse, Error>) -> WebDriverError {
+        let e = result.err().expect("expected an error");
+        let e = e
+            .downcast_ref::<WebDriverError>()
+            .expect("not a WebDriverError");
+        WebDriverError {
+            status: e.status,
+            error: e.error.clone(),
+            message: e.message.clone(),
+        }
+    }
+
+    #[test]
+    fn parse_w3c_responses() {
+        let resp = parse_response(StatusCode::OK, br#"{"value":{"title":"x"}}"#).unwrap();
+        assert_eq!(resp.value, json!({"title": "x"}));
+        assert_eq!(resp.status, None);
+
+        let resp = parse_response(StatusCode::OK, br#"{"value":null}"#).unwrap();
+        assert_eq!(resp.value, serde_json::Value::Null);
+
+        let err = wd_error_of(parse_response(
+            StatusCode::NOT_FOUND,
+            br#"{"value":{"error":"no such element","message":"gone","stacktrace":""}}"#,
+        ));
+        assert_eq!(err.status, 404);
+        assert_eq!(err.error, "no such element");
+        assert_eq!(err.message, "gone");
+
+        let err = parse_response(StatusCode::BAD_GATEWAY, b"<html>")
+            .err()
+            .unwrap();
+        assert!(err.downcast_ref::<WebDriverError>().is_none());
+    }
+
+    #[test]
+    fn parse_legacy_responses() {
+        let resp = parse_response(
+            StatusCode::OK,
+            br#"{"sessionId":"abc","status":0,"value":"Title"}"#,
+        )
+        .unwrap();
+        assert_eq!(resp.value, json!("Title"));
+        assert_eq!(resp.session_id.as_deref(), Some("abc"));
+
+        // Errors may be sent with a successful HTTP status.
+        let err = wd_error_of(parse_response(
+            StatusCode::OK,
+            br#"{"sessionId":"abc","status":7,"value":{"message":"Unable to locate element"}}"#,
+        ));
+        assert_eq!(err.status, 200);
+        assert_eq!(err.error, "no such element");
+        assert_eq!(err.message, "Unable to locate element");
+
+        let err = wd_error_of(parse_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            br#"{"status":10,"value":null}"#,
+        ));
+        assert_eq!(err.error, "stale element reference");
+        assert_eq!(err.message, "");
+    }
+
+    #[test]
+    fn map_legacy_error_codes() {
+        assert_eq!(legacy_error_code(7), "no such element");
+        assert_eq!(legacy_error_code(10), "stale element reference");
+        assert_eq!(legacy_error_code(12), "invalid element state");
+        assert_eq!(legacy_error_code(15), "invalid element state");
+        assert_eq!(legacy_error_code(19), "invalid selector");
+        assert_eq!(legacy_error_code(32), "invalid selector");
+        assert_eq!(legacy_error_code(28), "script timeout");
+        assert_eq!(legacy_error_code(13), "unknown error");
+        assert_eq!(legacy_error_code(-1), "unknown error");
+    }
+
+    #[test]
+    fn new_session_dialects() {
+        let resp = parse_response(
+            StatusCode::OK,
+            br#"{"value":{"sessionId":"w3c","capabilities":{"browserName":"firefox"}}}"#,
+        )
+        .unwrap();
+        let (session, dialect) = resp.into_session().unwrap();
+        assert_eq!(session.session_id, "w3c");
+        assert_eq!(dialect, Dialect::W3C);
+
+        let resp = parse_response(
+            StatusCode::OK,
+            br#"{"sessionId":"old","status":0,"value":{"browserName":"chrome"}}"#,
+        )
+        .unwrap();
+        let (session, dialect) = resp.into_session().unwrap();
+        assert_eq!(session.session_id, "old");
+        assert_eq!(session.capabilities["browserName"], json!("chrome"));
+        assert_eq!(dialect, Dialect::Legacy);
+    }
+
+    #[test]
+    fn element_references() {
+        let w3c: WebElement =
+            serde_json::from_value(json!({"element-6066-11e4-a52e-4f735466cecf": "a"})).unwrap();
+        assert_eq!(w3c.element_id, "a");
+        let legacy: WebElement = serde_json::from_value(json!({"ELEMENT": "b"})).unwrap();
+        assert_eq!(legacy.element_id, "b");
+        let both: WebElement = serde_json::from_value(json!({
+            "element-6066-11e4-a52e-4f735466cecf": "c",
+            "ELEMENT": "d",
+        }))
+        .unwrap();
+        assert_eq!(both.element_id, "c");
+        assert!(serde_json::from_value::<WebElement>(json!({})).is_err());
+
+        assert_eq!(
+            serde_json::to_value(&w3c).unwrap(),
+            json!({"element-6066-11e4-a52e-4f735466cecf": "a", "ELEMENT": "a"})
+        );
+        let list: Vec<WebElement> =
+            serde_json::from_value(json!([{"ELEMENT": "1"}, {"ELEMENT": "2"}])).unwrap();
+        assert_eq!(list.len(), 2);
+    }
+
+    #[test]
+    fn driver_log_records() {
+        let (target, level, msg) = driver_log_record(
+            "1588000000000\tgeckodriver\tINFO\tListening on 127.0.0.1:4444",
+            Level::Debug,
+        );
+        assert_eq!(target, "webdriver::geckodriver");
+        assert_eq!(level, Level::Info);
+        assert_eq!(msg, "Listening on 127.0.0.1:4444");
+
+        let (target, level, msg) = driver_log_record(
+            "1588000000000\tMarionette\tWARN\tTLS certificate errors\twill be ignored",
+            Level::Debug,
+        );
+        assert_eq!(target, "webdriver::geckodriver::marionette");
+        assert_eq!(level, Level::Warn);
+        assert_eq!(msg, "TLS certificate errors\twill be ignored");
+
+        let (_, level, _) = driver_log_record("1\tgeckodriver\tFATAL\tx", Level::Debug);
+        assert_eq!(level, Level::Error);
+        let (_, level, _) = driver_log_record("1\tgeckodriver\tCONFIG\tx", Level::Debug);
+        assert_eq!(level, Level::Info);
+
+        // Browser output and unknown levels use the default level.
+        for line in &[
+            "console.log: hello",
+            "abc\tgeckodriver\tINFO\tnot a timestamp",
+            "1\tgeckodriver\tNOTICE\tunknown level",
+        ] {
+            let (target, level, msg) = driver_log_record(line, Level::Debug);
+            assert_eq!(target, "webdriver::geckodriver");
+            assert_eq!(level, Level::Debug);
+            assert_eq!(msg, *line);
+        }
+    }
 }
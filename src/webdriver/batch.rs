@@ -0,0 +1,95 @@
+//! Reading values from many elements in one round trip.
+
+use super::{WebDriver, WebElement};
+use failure::Error;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+const READ_SCRIPT: &str = r#"
+const [elements, spec] = arguments;
+return elements.map(el => {
+    const out = { attributes: {}, properties: {}, text: null };
+    for (const name of spec.attributes) out.attributes[name] = el.getAttribute(name);
+    for (const path of spec.properties) {
+        out.properties[path] = path.split(".").reduce((v, key) => v == null ? null : v[key], el);
+    }
+    if (spec.text) out.text = el.innerText;
+    return out;
+});
+"#;
+
+/// The values [`WebDriver::read_elements`] reads from each element.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct ReadSpec {
+    attributes: Vec<String>,
+    properties: Vec<String>,
+    text: bool,
+}
+
+impl ReadSpec {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads the attribute `name`.
+    pub fn attr<T: Into<String>>(mut self, name: T) -> Self {
+        self.attributes.push(name.into());
+        self
+    }
+
+    /// Reads the property at `path`, which may be a dotted path such as
+    /// `parentNode.innerText`.
+    pub fn prop<T: Into<String>>(mut self, path: T) -> Self {
+        self.properties.push(path.into());
+        self
+    }
+
+    /// Reads the rendered text.
+    pub fn text(mut self) -> Self {
+        self.text = true;
+        self
+    }
+}
+
+/// Values read from one element.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ElementData {
+    /// Attribute values, `None` if the attribute is absent
+    pub attributes: HashMap<String, Option<String>>,
+    pub properties: HashMap<String, serde_json::Value>,
+    /// Rendered text, if requested
+    pub text: Option<String>,
+}
+
+impl ElementData {
+    /// Returns the value of attribute `name`, if present.
+    pub fn attr(&self, name: &str) -> Option<&str> {
+        self.attributes.get(name).and_then(|x| x.as_deref())
+    }
+
+    /// Deserializes the property read from `path`.
+    pub fn prop<T: DeserializeOwned>(&self, path: &str) -> Result<T, Error> {
+        match self.properties.get(path) {
+            Some(value) => Ok(serde_json::from_value(value.clone())?),
+            None => failure::bail!("property {:?} was not read", path),
+        }
+    }
+}
+
+impl WebDriver {
+    /// Reads the values in `spec` from every element in `elements` with a
+    /// single script call. The results are in the same order as
+    /// `elements`.
+    pub async fn read_elements(
+        &self,
+        elements: &[WebElement],
+        spec: &ReadSpec,
+    ) -> Result<Vec<ElementData>, Error> {
+        if elements.is_empty() {
+            return Ok(Vec::new());
+        }
+        let args = vec![serde_json::to_value(elements)?, serde_json::to_value(spec)?];
+        self.run_script(READ_SCRIPT, args).await
+    }
+}
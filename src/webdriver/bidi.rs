@@ -0,0 +1,173 @@
+//! WebDriver BiDi event subscriptions.
+//!
+//! The session must be created with `webSocketUrl` enabled, see
+//! [`CapabilitiesBuilder::web_socket_url`](super::CapabilitiesBuilder::web_socket_url).
+
+use super::WebDriver;
+use failure::Error;
+use futures::future::ready;
+use futures::stream::{Stream, StreamExt};
+use futures::SinkExt;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::pin::Pin;
+use tokio_tungstenite::connect_async;
+use tokio_tungstenite::tungstenite::Message;
+
+/// Events subscribed to by [`WebDriver::bidi_events`].
+pub const EVENTS: &[&str] = &[
+    "log.entryAdded",
+    "network.responseCompleted",
+    "browsingContext.load",
+];
+
+pub type BidiEvents = Pin<Box<dyn Stream<Item = Result<BidiEvent, Error>> + Send>>;
+
+#[derive(Debug, Clone)]
+pub enum BidiEvent {
+    /// A console message or JavaScript error (`log.entryAdded`)
+    LogEntry(LogEntry),
+    /// A network response finished loading (`network.responseCompleted`)
+    ResponseCompleted(ResponseCompleted),
+    /// A browsing context fired its load event (`browsingContext.load`)
+    Load(NavigationInfo),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct LogEntry {
+    /// `debug`, `info`, `warn` or `error`
+    pub level: String,
+    /// `console` or `javascript`
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub text: Option<String>,
+    pub timestamp: u64,
+    /// Console method, for `console` entries
+    #[serde(default)]
+    pub method: Option<String>,
+    #[serde(default)]
+    pub stack_trace: Option<serde_json::Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ResponseCompleted {
+    pub context: Option<String>,
+    pub navigation: Option<String>,
+    pub timestamp: u64,
+    pub request: RequestData,
+    pub response: ResponseData,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct RequestData {
+    pub request: String,
+    pub url: String,
+    pub method: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ResponseData {
+    pub url: String,
+    pub status: u16,
+    pub status_text: String,
+    #[serde(default)]
+    pub mime_type: Option<String>,
+    #[serde(default)]
+    pub from_cache: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct NavigationInfo {
+    pub context: String,
+    pub navigation: Option<String>,
+    pub timestamp: u64,
+    pub url: String,
+}
+
+#[derive(Deserialize)]
+struct BidiMessage {
+    id: Option<u64>,
+    #[serde(rename = "type")]
+    kind: Option<String>,
+    method: Option<String>,
+    params: Option<serde_json::Value>,
+    error: Option<String>,
+    message: Option<String>,
+}
+
+impl BidiMessage {
+    fn into_event(self) -> Result<Option<BidiEvent>, Error> {
+        let params = match self.params {
+            Some(params) => params,
+            None => return Ok(None),
+        };
+        let event = match self.method.as_deref() {
+            Some("log.entryAdded") => BidiEvent::LogEntry(serde_json::from_value(params)?),
+            Some("network.responseCompleted") => {
+                BidiEvent::ResponseCompleted(serde_json::from_value(params)?)
+            }
+            Some("browsingContext.load") => BidiEvent::Load(serde_json::from_value(params)?),
+            _ => return Ok(None),
+        };
+        Ok(Some(event))
+    }
+}
+
+impl WebDriver {
+    /// Opens a BiDi connection and subscribes to [`EVENTS`].
+    ///
+    /// Each call opens a new connection, so every stream receives all
+    /// events emitted after it was created.
+    pub async fn bidi_events(&self) -> Result<BidiEvents, Error> {
+        let url = self
+            .web_socket_url()
+            .ok_or_else(|| failure::format_err!("session has no BiDi WebSocket URL"))?;
+        let (mut ws, _) = connect_async(url).await?;
+        let subscribe = json!({
+            "id": 1,
+            "method": "session.subscribe",
+            "params": {
+                "events": EVENTS,
+            },
+        });
+        ws.send(Message::Text(subscribe.to_string())).await?;
+        loop {
+            let msg = match ws.next().await {
+                Some(msg) => msg,
+                None => failure::bail!("bidi connection closed before subscribing"),
+            };
+            let text = match msg? {
+                Message::Text(text) => text,
+                _ => continue,
+            };
+            let msg = serde_json::from_str::<BidiMessage>(&text)?;
+            if msg.id != Some(1) {
+                continue;
+            }
+            if msg.kind.as_deref() == Some("error") {
+                failure::bail!(
+                    "bidi subscribe failed: {}: {}",
+                    msg.error.unwrap_or_default(),
+                    msg.message.unwrap_or_default()
+                );
+            }
+            break;
+        }
+        let events = ws.filter_map(|msg| {
+            ready(match msg {
+                Ok(Message::Text(text)) => serde_json::from_str::<BidiMessage>(&text)
+                    .map_err(Error::from)
+                    .and_then(BidiMessage::into_event)
+                    .transpose(),
+                Ok(_) => None,
+                Err(e) => Some(Err(e.into())),
+            })
+        });
+        Ok(Box::pin(events))
+    }
+}
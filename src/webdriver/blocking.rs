@@ -0,0 +1,559 @@
+//! Blocking wrapper around the async [`WebDriver`](super::WebDriver).
+//!
+//! Each `WebDriver` owns a small tokio runtime which drives the async client
+//! and the driver process, except for pooled sessions which share the
+//! pool's runtime. Commands are run on the calling thread, so sessions
+//! sharing a runtime can be used from several threads at once. It must not
+//! be used from within another runtime.
+
+use super::batch::{ElementData, ReadSpec};
+#[cfg(feature = "bidi")]
+use super::bidi::{BidiEvent, BidiEvents};
+use super::downloads::DownloadDir;
+use super::emulation::{DevicePreset, Viewport};
+use super::extract::Field;
+use super::locator::Locator;
+#[cfg(feature = "proxy")]
+use super::proxy;
+use super::visual::{VisualDiff, VisualOptions};
+use super::{
+    pool, CapabilitiesBuilder, Dialect, Method, RetryPolicy, Using, WebElement, WindowRect,
+};
+use failure::Error;
+#[cfg(feature = "bidi")]
+use futures::stream::StreamExt;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::future::Future;
+use std::ops::{Deref, DerefMut};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::runtime::{Builder, Runtime};
+
+/// A blocking session, or with `D` = [`pool::PooledWebDriver`](super::pool::PooledWebDriver)
+/// a session borrowed from a [`WebDriverPool`].
+pub struct WebDriver<D = super::WebDriver> {
+    inner: D,
+    rt: Arc<Runtime>,
+}
+
+fn runtime() -> Result<Runtime, Error> {
+    Ok(Builder::new()
+        .threaded_scheduler()
+        .core_threads(1)
+        .enable_all()
+        .build()?)
+}
+
+impl WebDriver {
+    pub fn new_firefox<T>(command: Option<T>, headless: bool, verbose: bool) -> Result<Self, Error>
+    where
+        T: Into<String>,
+    {
+        let mut rt = runtime()?;
+        let inner = rt.block_on(super::WebDriver::new_firefox(command, headless, verbose))?;
+        Ok(Self::from_parts(inner, rt))
+    }
+
+    pub fn new_firefox_with_caps<T>(
+        command: Option<T>,
+        caps: CapabilitiesBuilder,
+        verbose: bool,
+    ) -> Result<Self, Error>
+    where
+        T: Into<String>,
+    {
+        let mut rt = runtime()?;
+        let inner = rt.block_on(super::WebDriver::new_firefox_with_caps(
+            command, caps, verbose,
+        ))?;
+        Ok(Self::from_parts(inner, rt))
+    }
+
+    pub fn new<T: Into<String> + std::fmt::Display>(
+        url: T,
+        always_match: HashMap<String, serde_json::Value>,
+        first_match: Vec<HashMap<String, serde_json::Value>>,
+    ) -> Result<Self, Error> {
+        let mut rt = runtime()?;
+        let inner = rt.block_on(super::WebDriver::new(url, always_match, first_match))?;
+        Ok(Self::from_parts(inner, rt))
+    }
+
+    fn from_parts(inner: super::WebDriver, rt: Runtime) -> Self {
+        Self {
+            inner,
+            rt: Arc::new(rt),
+        }
+    }
+
+    pub fn close(self) -> Result<(), Error> {
+        let Self { inner, rt } = self;
+        block_on(&rt, inner.close())
+    }
+}
+
+/// Runs `future` on the calling thread with `rt` providing timers and I/O.
+/// Unlike `Runtime::block_on`, this does not need exclusive access to the
+/// runtime.
+fn block_on<F: Future>(rt: &Runtime, future: F) -> F::Output {
+    rt.handle().enter(|| futures::executor::block_on(future))
+}
+
+impl<'a> PooledWebDriver<'a> {
+    /// Removes the session from the pool instead of returning it, e.g.
+    /// after it broke. The session keeps using the pool's runtime.
+    pub fn discard(self) -> WebDriver {
+        WebDriver {
+            inner: self.inner.discard(),
+            rt: self.rt,
+        }
+    }
+}
+
+impl<D> WebDriver<D>
+where
+    D: Deref<Target = super::WebDriver>,
+{
+    fn block_on<F: Future>(&self, future: F) -> F::Output {
+        block_on(&self.rt, future)
+    }
+
+    pub fn web_socket_url(&self) -> Option<&str> {
+        self.inner.web_socket_url()
+    }
+
+    pub fn dialect(&self) -> Dialect {
+        self.inner.dialect()
+    }
+
+    pub fn retry_policy(&self) -> &RetryPolicy {
+        self.inner.retry_policy()
+    }
+
+    pub fn set_retry_policy(&mut self, retry: RetryPolicy)
+    where
+        D: DerefMut,
+    {
+        self.inner.set_retry_policy(retry)
+    }
+
+    pub fn send_command<T: DeserializeOwned>(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<serde_json::Value>,
+    ) -> Result<T, Error> {
+        self.block_on(self.inner.send_command(method, path, body))
+    }
+
+    pub fn get_elements<T: Into<String>>(
+        &self,
+        using: Using,
+        value: T,
+    ) -> Result<Vec<WebElement>, Error> {
+        self.block_on(self.inner.get_elements(using, value))
+    }
+
+    pub fn get_element<T: Into<String>>(
+        &self,
+        using: Using,
+        value: T,
+    ) -> Result<WebElement, Error> {
+        self.block_on(self.inner.get_element(using, value))
+    }
+
+    pub fn get_elements_from_element<T: Into<String>>(
+        &self,
+        element: &WebElement,
+        using: Using,
+        value: T,
+    ) -> Result<Vec<WebElement>, Error> {
+        self.block_on(self.inner.get_elements_from_element(element, using, value))
+    }
+
+    pub fn get_element_from_element<T: Into<String>>(
+        &self,
+        element: &WebElement,
+        using: Using,
+        value: T,
+    ) -> Result<WebElement, Error> {
+        self.block_on(self.inner.get_element_from_element(element, using, value))
+    }
+
+    pub fn element_click(&self, element: &WebElement) -> Result<(), Error> {
+        self.block_on(self.inner.element_click(element))
+    }
+
+    pub fn get_element_attr(&self, element: &WebElement, attr: &str) -> Result<String, Error> {
+        self.block_on(self.inner.get_element_attr(element, attr))
+    }
+
+    pub fn get_element_prop<T: DeserializeOwned>(
+        &self,
+        element: &WebElement,
+        prop: &str,
+    ) -> Result<T, Error> {
+        self.block_on(self.inner.get_element_prop(element, prop))
+    }
+
+    pub fn get_element_text(&self, element: &WebElement) -> Result<String, Error> {
+        self.block_on(self.inner.get_element_text(element))
+    }
+
+    pub fn element_send_keys<T: Into<String>>(
+        &self,
+        element: &WebElement,
+        keys: T,
+    ) -> Result<(), Error> {
+        self.block_on(self.inner.element_send_keys(element, keys))
+    }
+
+    pub fn run_script_unit<T>(&self, script: T) -> Result<(), Error>
+    where
+        T: Into<String> + Serialize,
+    {
+        self.block_on(self.inner.run_script_unit(script))
+    }
+
+    pub fn run_script_elem<T, V>(&self, script: T, element: &WebElement) -> Result<V, Error>
+    where
+        T: Into<String>,
+        V: DeserializeOwned,
+    {
+        self.block_on(self.inner.run_script_elem(script, element))
+    }
+
+    pub fn run_script<T, V>(&self, script: T, args: Vec<serde_json::Value>) -> Result<V, Error>
+    where
+        T: Into<String>,
+        V: DeserializeOwned,
+    {
+        self.block_on(self.inner.run_script(script, args))
+    }
+
+    pub fn get_window_rect(&self) -> Result<WindowRect, Error> {
+        self.block_on(self.inner.get_window_rect())
+    }
+
+    pub fn set_window_rect(
+        &self,
+        x: Option<i32>,
+        y: Option<i32>,
+        width: Option<u32>,
+        height: Option<u32>,
+    ) -> Result<WindowRect, Error> {
+        self.block_on(self.inner.set_window_rect(x, y, width, height))
+    }
+
+    pub fn viewport_size(&self) -> Result<Viewport, Error> {
+        self.block_on(self.inner.viewport_size())
+    }
+
+    pub fn set_viewport_size(&self, width: u32, height: u32) -> Result<Viewport, Error> {
+        self.block_on(self.inner.set_viewport_size(width, height))
+    }
+
+    pub fn emulate(&self, preset: &DevicePreset) -> Result<Viewport, Error> {
+        self.block_on(self.inner.emulate(preset))
+    }
+
+    pub fn navigate<T: Into<String>>(&self, url: T) -> Result<(), Error> {
+        self.block_on(self.inner.navigate(url))
+    }
+
+    pub fn get_title(&self) -> Result<String, Error> {
+        self.block_on(self.inner.get_title())
+    }
+
+    pub fn get_current_url(&self) -> Result<String, Error> {
+        self.block_on(self.inner.get_current_url())
+    }
+
+    pub fn screenshot(&self) -> Result<Vec<u8>, Error> {
+        self.block_on(self.inner.screenshot())
+    }
+
+    pub fn element_screenshot(&self, element: &WebElement) -> Result<Vec<u8>, Error> {
+        self.block_on(self.inner.element_screenshot(element))
+    }
+
+    pub fn wait_for_element<T: Into<String>>(
+        &self,
+        using: Using,
+        value: T,
+        timeout: Duration,
+    ) -> Result<WebElement, Error> {
+        self.block_on(self.inner.wait_for_element(using, value, timeout))
+    }
+
+    pub fn wait_for_elements<T: Into<String>>(
+        &self,
+        using: Using,
+        value: T,
+        timeout: Duration,
+    ) -> Result<Vec<WebElement>, Error> {
+        self.block_on(self.inner.wait_for_elements(using, value, timeout))
+    }
+
+    pub fn find(&self, locator: &Locator) -> Result<WebElement, Error> {
+        self.block_on(self.inner.find(locator))
+    }
+
+    pub fn find_all(&self, locator: &Locator) -> Result<Vec<WebElement>, Error> {
+        self.block_on(self.inner.find_all(locator))
+    }
+
+    pub fn find_in(&self, element: &WebElement, locator: &Locator) -> Result<WebElement, Error> {
+        self.block_on(self.inner.find_in(element, locator))
+    }
+
+    pub fn find_all_in(
+        &self,
+        element: &WebElement,
+        locator: &Locator,
+    ) -> Result<Vec<WebElement>, Error> {
+        self.block_on(self.inner.find_all_in(element, locator))
+    }
+
+    pub fn wait_for(&self, locator: &Locator, timeout: Duration) -> Result<WebElement, Error> {
+        self.block_on(self.inner.wait_for(locator, timeout))
+    }
+
+    pub fn wait_for_all(
+        &self,
+        locator: &Locator,
+        timeout: Duration,
+    ) -> Result<Vec<WebElement>, Error> {
+        self.block_on(self.inner.wait_for_all(locator, timeout))
+    }
+
+    pub fn delete_all_cookies(&self) -> Result<(), Error> {
+        self.block_on(self.inner.delete_all_cookies())
+    }
+
+    pub fn select_options(&self, select: &WebElement) -> Result<Vec<WebElement>, Error> {
+        self.block_on(self.inner.select_options(select))
+    }
+
+    pub fn select_by_value(&self, select: &WebElement, value: &str) -> Result<(), Error> {
+        self.block_on(self.inner.select_by_value(select, value))
+    }
+
+    pub fn select_by_index(&self, select: &WebElement, index: usize) -> Result<(), Error> {
+        self.block_on(self.inner.select_by_index(select, index))
+    }
+
+    pub fn select_by_text(&self, select: &WebElement, text: &str) -> Result<(), Error> {
+        self.block_on(self.inner.select_by_text(select, text))
+    }
+
+    pub fn selected_options(&self, select: &WebElement) -> Result<Vec<WebElement>, Error> {
+        self.block_on(self.inner.selected_options(select))
+    }
+
+    pub fn radio_inputs(&self, container: &WebElement) -> Result<Vec<WebElement>, Error> {
+        self.block_on(self.inner.radio_inputs(container))
+    }
+
+    pub fn checked_radio(&self, container: &WebElement) -> Result<Option<WebElement>, Error> {
+        self.block_on(self.inner.checked_radio(container))
+    }
+
+    pub fn check_radio(&self, radio: &WebElement) -> Result<(), Error> {
+        self.block_on(self.inner.check_radio(radio))
+    }
+
+    pub fn set_checkbox(&self, checkbox: &WebElement, checked: bool) -> Result<(), Error> {
+        self.block_on(self.inner.set_checkbox(checkbox, checked))
+    }
+
+    pub fn upload_file<P: AsRef<Path>>(&self, input: &WebElement, path: P) -> Result<(), Error> {
+        self.block_on(self.inner.upload_file(input, path))
+    }
+
+    pub fn read_elements(
+        &self,
+        elements: &[WebElement],
+        spec: &ReadSpec,
+    ) -> Result<Vec<ElementData>, Error> {
+        self.block_on(self.inner.read_elements(elements, spec))
+    }
+
+    pub fn extract_table(&self, table: &WebElement) -> Result<Vec<HashMap<String, String>>, Error> {
+        self.block_on(self.inner.extract_table(table))
+    }
+
+    pub fn extract_table_as<T: DeserializeOwned>(
+        &self,
+        table: &WebElement,
+    ) -> Result<Vec<T>, Error> {
+        self.block_on(self.inner.extract_table_as(table))
+    }
+
+    pub fn extract_list(
+        &self,
+        selector: &str,
+        fields: &[Field],
+        root: Option<&WebElement>,
+    ) -> Result<Vec<HashMap<String, String>>, Error> {
+        self.block_on(self.inner.extract_list(selector, fields, root))
+    }
+
+    pub fn extract_list_as<T: DeserializeOwned>(
+        &self,
+        selector: &str,
+        fields: &[Field],
+        root: Option<&WebElement>,
+    ) -> Result<Vec<T>, Error> {
+        self.block_on(self.inner.extract_list_as(selector, fields, root))
+    }
+
+    /// Waits for a download into `dir`, see [`DownloadDir::wait_for_download`].
+    pub fn wait_for_download(
+        &self,
+        dir: &DownloadDir,
+        pattern: &str,
+        timeout: Duration,
+    ) -> Result<PathBuf, Error> {
+        self.block_on(dir.wait_for_download(pattern, timeout))
+    }
+
+    /// Takes a screenshot and compares it against `baseline`, see
+    /// [`visual::compare`](super::visual::compare).
+    pub fn compare_screenshot(
+        &self,
+        baseline: &Path,
+        diff_path: Option<&Path>,
+        options: &VisualOptions,
+    ) -> Result<VisualDiff, Error> {
+        self.block_on(self.inner.compare_screenshot(baseline, diff_path, options))
+    }
+
+    /// Subscribes to BiDi events, see
+    /// [`WebDriver::bidi_events`](super::WebDriver::bidi_events). The
+    /// iterator blocks until the next event arrives.
+    #[cfg(feature = "bidi")]
+    pub fn bidi_events(&self) -> Result<BidiEventIter<'_, D>, Error> {
+        let events = self.block_on(self.inner.bidi_events())?;
+        Ok(BidiEventIter {
+            driver: self,
+            events,
+        })
+    }
+}
+
+/// Blocking iterator over BiDi events.
+#[cfg(feature = "bidi")]
+pub struct BidiEventIter<'a, D> {
+    driver: &'a WebDriver<D>,
+    events: BidiEvents,
+}
+
+#[cfg(feature = "bidi")]
+impl<D> Iterator for BidiEventIter<'_, D>
+where
+    D: Deref<Target = super::WebDriver>,
+{
+    type Item = Result<BidiEvent, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let events = &mut self.events;
+        self.driver.block_on(events.next())
+    }
+}
+
+/// Blocking wrapper around [`pool::WebDriverPool`](super::pool::WebDriverPool).
+///
+/// All sessions share the pool's runtime.
+pub struct WebDriverPool {
+    inner: pool::WebDriverPool,
+    rt: Arc<Runtime>,
+}
+
+/// A session borrowed from a [`WebDriverPool`], returned to it when
+/// dropped.
+pub type PooledWebDriver<'a> = WebDriver<pool::PooledWebDriver<'a>>;
+
+impl WebDriverPool {
+    pub fn new_firefox<T>(
+        size: usize,
+        command: Option<T>,
+        caps: CapabilitiesBuilder,
+        verbose: bool,
+    ) -> Result<Self, Error>
+    where
+        T: Into<String>,
+    {
+        Ok(Self {
+            inner: pool::WebDriverPool::new_firefox(size, command, caps, verbose),
+            rt: Arc::new(runtime()?),
+        })
+    }
+
+    pub fn size(&self) -> usize {
+        self.inner.size()
+    }
+
+    /// Number of sessions waiting to be reused.
+    pub fn idle(&self) -> usize {
+        self.inner.idle()
+    }
+
+    /// Takes a session from the pool, waiting until one is available.
+    pub fn get(&self) -> Result<PooledWebDriver<'_>, Error> {
+        let inner = block_on(&self.rt, self.inner.get())?;
+        Ok(WebDriver {
+            inner,
+            rt: self.rt.clone(),
+        })
+    }
+
+    /// Closes all idle sessions.
+    pub fn close(self) -> Result<(), Error> {
+        let Self { inner, rt } = self;
+        block_on(&rt, inner.close())
+    }
+}
+
+/// Blocking wrapper around [`proxy::RecordingProxy`](super::proxy::RecordingProxy),
+/// which runs on its own runtime. The recorded entries are read through
+/// `Deref`.
+#[cfg(feature = "proxy")]
+pub struct RecordingProxy {
+    inner: proxy::RecordingProxy,
+    // Dropped after the proxy so its shutdown is delivered.
+    _rt: Runtime,
+}
+
+#[cfg(feature = "proxy")]
+impl RecordingProxy {
+    /// Starts a proxy that records every request, see
+    /// [`proxy::RecordingProxy::start`](super::proxy::RecordingProxy::start).
+    pub fn start() -> Result<Self, Error> {
+        Self::with_filters(Vec::<String>::new())
+    }
+
+    /// Starts a proxy that only records requests matching `patterns`, see
+    /// [`proxy::RecordingProxy::with_filters`](super::proxy::RecordingProxy::with_filters).
+    pub fn with_filters<I, T>(patterns: I) -> Result<Self, Error>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<String>,
+    {
+        let rt = runtime()?;
+        let inner = block_on(&rt, proxy::RecordingProxy::with_filters(patterns))?;
+        Ok(Self { inner, _rt: rt })
+    }
+}
+
+#[cfg(feature = "proxy")]
+impl Deref for RecordingProxy {
+    type Target = proxy::RecordingProxy;
+
+    fn deref(&self) -> &proxy::RecordingProxy {
+        &self.inner
+    }
+}
@@ -0,0 +1,87 @@
+//! Per-session download directories.
+
+use super::glob::glob_match;
+use failure::Error;
+use rand::distributions::Alphanumeric;
+use rand::prelude::*;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tokio::time::delay_for;
+
+/// Extensions of files that are still being downloaded.
+const PARTIAL_EXTENSIONS: &[&str] = &["part", "crdownload"];
+
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// A temporary directory that receives a session's downloads.
+///
+/// Pass [`path`](DownloadDir::path) to
+/// [`CapabilitiesBuilder::download_dir`](super::CapabilitiesBuilder::download_dir)
+/// when creating the session. The directory and its contents are removed
+/// when this is dropped.
+pub struct DownloadDir {
+    path: PathBuf,
+}
+
+impl DownloadDir {
+    pub fn new() -> io::Result<Self> {
+        let name: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(16)
+            .collect();
+        let path = std::env::temp_dir().join(format!("lmaobgd-downloads-{}", name));
+        fs::create_dir(&path)?;
+        Ok(Self { path })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Waits until a file whose name matches `pattern` has finished
+    /// downloading and returns its path.
+    ///
+    /// `pattern` is a glob where `*` matches any run of characters and `?`
+    /// matches a single character. A download is finished when the file
+    /// exists and no partial download files remain in the directory.
+    pub async fn wait_for_download(
+        &self,
+        pattern: &str,
+        timeout: Duration,
+    ) -> Result<PathBuf, Error> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let mut found = None;
+            let mut partial = false;
+            for entry in fs::read_dir(&self.path)? {
+                let path = entry?.path();
+                let is_partial = matches!(
+                    path.extension().and_then(|ext| ext.to_str()),
+                    Some(ext) if PARTIAL_EXTENSIONS.contains(&ext)
+                );
+                if is_partial {
+                    partial = true;
+                } else if let Some(name) = path.file_name().and_then(|name| name.to_str()) {
+                    if glob_match(pattern, name) {
+                        found = Some(path);
+                    }
+                }
+            }
+            match found {
+                Some(path) if !partial => return Ok(path),
+                _ if Instant::now() >= deadline => {
+                    failure::bail!("timed out waiting for download matching {:?}", pattern)
+                }
+                _ => delay_for(POLL_INTERVAL).await,
+            }
+        }
+    }
+}
+
+impl Drop for DownloadDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
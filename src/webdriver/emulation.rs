@@ -0,0 +1,123 @@
+//! Viewport sizing and device presets.
+
+use super::{CapabilitiesBuilder, WebDriver};
+use failure::Error;
+use serde::{Deserialize, Serialize};
+
+/// A named viewport size and user agent.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DevicePreset {
+    pub name: &'static str,
+    /// Viewport width in CSS pixels
+    pub width: u32,
+    /// Viewport height in CSS pixels
+    pub height: u32,
+    /// User agent to send, or `None` to keep the browser's own
+    pub user_agent: Option<&'static str>,
+}
+
+pub const PHONE: DevicePreset = DevicePreset {
+    name: "phone",
+    width: 375,
+    height: 667,
+    user_agent: Some(
+        "Mozilla/5.0 (iPhone; CPU iPhone OS 13_3 like Mac OS X) AppleWebKit/605.1.15 \
+         (KHTML, like Gecko) Version/13.0.5 Mobile/15E148 Safari/604.1",
+    ),
+};
+
+pub const TABLET: DevicePreset = DevicePreset {
+    name: "tablet",
+    width: 768,
+    height: 1024,
+    user_agent: Some(
+        "Mozilla/5.0 (iPad; CPU OS 13_3 like Mac OS X) AppleWebKit/605.1.15 \
+         (KHTML, like Gecko) Version/13.0.5 Mobile/15E148 Safari/604.1",
+    ),
+};
+
+pub const DESKTOP: DevicePreset = DevicePreset {
+    name: "desktop",
+    width: 1366,
+    height: 768,
+    user_agent: None,
+};
+
+pub const PRESETS: &[DevicePreset] = &[PHONE, TABLET, DESKTOP];
+
+/// Looks up a preset by name.
+pub fn preset(name: &str) -> Option<DevicePreset> {
+    PRESETS.iter().copied().find(|p| p.name == name)
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Viewport {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl CapabilitiesBuilder {
+    /// Applies the user agent of `preset`.
+    ///
+    /// The user agent can only be set at session creation, so the viewport
+    /// still has to be set with [`WebDriver::emulate`] afterwards.
+    pub fn device(self, preset: &DevicePreset) -> Self {
+        match preset.user_agent {
+            Some(user_agent) => self.user_agent(user_agent),
+            None => self,
+        }
+    }
+}
+
+impl WebDriver {
+    /// Returns the size of the page viewport in CSS pixels.
+    pub async fn viewport_size(&self) -> Result<Viewport, Error> {
+        self.run_script(
+            "return { width: window.innerWidth, height: window.innerHeight };",
+            vec![],
+        )
+        .await
+    }
+
+    /// Resizes the window so that the viewport is exactly `width` by
+    /// `height` CSS pixels.
+    ///
+    /// The window size includes browser chrome such as toolbars, so the
+    /// window is resized and then corrected by the measured difference.
+    pub async fn set_viewport_size(&self, width: u32, height: u32) -> Result<Viewport, Error> {
+        let mut viewport = self.viewport_size().await?;
+        // The first pass measures the chrome, the second corrects for
+        // window managers that adjust the requested size.
+        for _ in 0..2 {
+            if viewport.width == width && viewport.height == height {
+                break;
+            }
+            let rect = self.get_window_rect().await?;
+            let chrome_width = rect.width.saturating_sub(viewport.width);
+            let chrome_height = rect.height.saturating_sub(viewport.height);
+            self.set_window_rect(
+                None,
+                None,
+                Some(width + chrome_width),
+                Some(height + chrome_height),
+            )
+            .await?;
+            viewport = self.viewport_size().await?;
+        }
+        if viewport.width != width || viewport.height != height {
+            failure::bail!(
+                "unable to set viewport to {}x{}, got {}x{}",
+                width,
+                height,
+                viewport.width,
+                viewport.height
+            );
+        }
+        Ok(viewport)
+    }
+
+    /// Sets the viewport to the size of `preset`.
+    pub async fn emulate(&self, preset: &DevicePreset) -> Result<Viewport, Error> {
+        self.set_viewport_size(preset.width, preset.height).await
+    }
+}
@@ -0,0 +1,162 @@
+//! Structured extraction of tables and repeated items.
+//!
+//! Each helper reads the whole structure with a single script call instead
+//! of one command per element.
+
+use super::{WebDriver, WebElement};
+use failure::Error;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+const TABLE_SCRIPT: &str = r#"
+const table = arguments[0];
+const rows = Array.from(table.rows);
+const grid = rows.map(() => []);
+rows.forEach((row, r) => {
+    let c = 0;
+    for (const cell of row.cells) {
+        while (grid[r][c] !== undefined) c++;
+        const rowSpan = cell.rowSpan === 0 ? rows.length - r : Math.max(cell.rowSpan, 1);
+        const colSpan = Math.max(cell.colSpan, 1);
+        const value = { text: cell.innerText.trim(), th: cell.tagName === "TH" };
+        for (let i = 0; i < rowSpan && r + i < rows.length; i++) {
+            for (let j = 0; j < colSpan; j++) grid[r + i][c + j] = value;
+        }
+        c += colSpan;
+    }
+});
+let headerRows = rows.filter(row => row.parentElement.tagName === "THEAD").length;
+if (headerRows === 0) {
+    while (headerRows < grid.length && grid[headerRows].length > 0
+        && grid[headerRows].every(cell => cell && cell.th)) headerRows++;
+}
+const width = Math.max(0, ...grid.map(row => row.length));
+const seen = {};
+const keys = [];
+for (let c = 0; c < width; c++) {
+    const parts = [];
+    for (let r = 0; r < headerRows; r++) {
+        const cell = grid[r][c];
+        if (cell && cell.text && parts[parts.length - 1] !== cell.text) parts.push(cell.text);
+    }
+    let key = parts.length ? parts.join(" ") : String(c);
+    seen[key] = (seen[key] || 0) + 1;
+    if (seen[key] > 1) key += "_" + seen[key];
+    keys.push(key);
+}
+return grid.slice(headerRows).map(row => {
+    const out = {};
+    keys.forEach((key, c) => {
+        if (row[c]) out[key] = row[c].text;
+    });
+    return out;
+});
+"#;
+
+const LIST_SCRIPT: &str = r#"
+const [selector, fields, root] = arguments;
+return Array.from((root || document).querySelectorAll(selector)).map(item => {
+    const out = {};
+    for (const field of fields) {
+        const el = field.selector ? item.querySelector(field.selector) : item;
+        if (!el) continue;
+        const value = field.attribute ? el.getAttribute(field.attribute) : el.innerText.trim();
+        if (value !== null) out[field.name] = value;
+    }
+    return out;
+});
+"#;
+
+/// A value read from each item by [`WebDriver::extract_list`].
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Field {
+    /// Key of the value in the extracted item
+    pub name: String,
+    /// CSS selector relative to the item, or empty for the item itself
+    pub selector: String,
+    /// Attribute to read instead of the text
+    pub attribute: Option<String>,
+}
+
+impl Field {
+    /// Reads the rendered text of the first match of `selector`.
+    pub fn text<N: Into<String>, S: Into<String>>(name: N, selector: S) -> Self {
+        Self {
+            name: name.into(),
+            selector: selector.into(),
+            attribute: None,
+        }
+    }
+
+    /// Reads `attribute` of the first match of `selector`.
+    pub fn attr<N, S, A>(name: N, selector: S, attribute: A) -> Self
+    where
+        N: Into<String>,
+        S: Into<String>,
+        A: Into<String>,
+    {
+        Self {
+            name: name.into(),
+            selector: selector.into(),
+            attribute: Some(attribute.into()),
+        }
+    }
+}
+
+impl WebDriver {
+    /// Reads the body rows of `table` as maps from column header to cell
+    /// text.
+    ///
+    /// Header rows are the rows in `<thead>`, or otherwise the leading rows
+    /// consisting only of `<th>` cells. Stacked headers are joined with
+    /// spaces and duplicates get a `_2`, `_3`, ... suffix. Columns without
+    /// a header are keyed by their index. Cells spanning several rows or
+    /// columns are repeated in each of them.
+    pub async fn extract_table(
+        &self,
+        table: &WebElement,
+    ) -> Result<Vec<HashMap<String, String>>, Error> {
+        self.extract_table_as(table).await
+    }
+
+    /// Like [`extract_table`](WebDriver::extract_table), deserializing each
+    /// row into `T`. All values are strings.
+    pub async fn extract_table_as<T: DeserializeOwned>(
+        &self,
+        table: &WebElement,
+    ) -> Result<Vec<T>, Error> {
+        self.run_script(TABLE_SCRIPT, vec![serde_json::to_value(table)?])
+            .await
+    }
+
+    /// Reads `fields` from every element matching the CSS `selector`,
+    /// optionally searching only under `root`.
+    ///
+    /// Fields whose selector does not match, or whose attribute is absent,
+    /// are left out of the item.
+    pub async fn extract_list(
+        &self,
+        selector: &str,
+        fields: &[Field],
+        root: Option<&WebElement>,
+    ) -> Result<Vec<HashMap<String, String>>, Error> {
+        self.extract_list_as(selector, fields, root).await
+    }
+
+    /// Like [`extract_list`](WebDriver::extract_list), deserializing each
+    /// item into `T`. All values are strings.
+    pub async fn extract_list_as<T: DeserializeOwned>(
+        &self,
+        selector: &str,
+        fields: &[Field],
+        root: Option<&WebElement>,
+    ) -> Result<Vec<T>, Error> {
+        let args = vec![
+            serde_json::to_value(selector)?,
+            serde_json::to_value(fields)?,
+            serde_json::to_value(root)?,
+        ];
+        self.run_script(LIST_SCRIPT, args).await
+    }
+}
@@ -0,0 +1,100 @@
+//! Helpers for interacting with form controls.
+
+use super::{Using, WebDriver, WebElement};
+use failure::Error;
+use std::path::Path;
+
+/// Key code sent to toggle radio inputs and checkboxes.
+const SPACE: &str = "\u{e00d}";
+
+impl WebDriver {
+    /// Returns the `<option>` elements of a `<select>`.
+    pub async fn select_options(&self, select: &WebElement) -> Result<Vec<WebElement>, Error> {
+        self.get_elements_from_element(select, Using::TagName, "option")
+            .await
+    }
+
+    /// Selects the option whose `value` property equals `value`.
+    pub async fn select_by_value(&self, select: &WebElement, value: &str) -> Result<(), Error> {
+        for option in self.select_options(select).await? {
+            if self.get_element_prop::<String>(&option, "value").await? == value {
+                return self.element_click(&option).await;
+            }
+        }
+        failure::bail!("no option with value {:?}", value)
+    }
+
+    /// Selects the option at `index`, counting from zero.
+    pub async fn select_by_index(&self, select: &WebElement, index: usize) -> Result<(), Error> {
+        let options = self.select_options(select).await?;
+        let option = options
+            .get(index)
+            .ok_or_else(|| failure::format_err!("no option at index {}", index))?;
+        self.element_click(option).await
+    }
+
+    /// Selects the option whose visible text equals `text`, ignoring
+    /// surrounding whitespace.
+    pub async fn select_by_text(&self, select: &WebElement, text: &str) -> Result<(), Error> {
+        for option in self.select_options(select).await? {
+            if self.get_element_text(&option).await?.trim() == text.trim() {
+                return self.element_click(&option).await;
+            }
+        }
+        failure::bail!("no option with text {:?}", text)
+    }
+
+    /// Returns the selected options of a `<select>`.
+    pub async fn selected_options(&self, select: &WebElement) -> Result<Vec<WebElement>, Error> {
+        let mut selected = Vec::new();
+        for option in self.select_options(select).await? {
+            if self.get_element_prop::<bool>(&option, "selected").await? {
+                selected.push(option);
+            }
+        }
+        Ok(selected)
+    }
+
+    /// Returns the radio inputs inside `container`.
+    pub async fn radio_inputs(&self, container: &WebElement) -> Result<Vec<WebElement>, Error> {
+        self.get_elements_from_element(container, Using::CssSelector, r#"input[type="radio"]"#)
+            .await
+    }
+
+    /// Returns the checked radio input inside `container`, if any.
+    pub async fn checked_radio(&self, container: &WebElement) -> Result<Option<WebElement>, Error> {
+        for radio in self.radio_inputs(container).await? {
+            if self.get_element_prop::<bool>(&radio, "checked").await? {
+                return Ok(Some(radio));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Checks a radio input.
+    pub async fn check_radio(&self, radio: &WebElement) -> Result<(), Error> {
+        self.element_send_keys(radio, SPACE).await
+    }
+
+    /// Checks or unchecks a checkbox, doing nothing if it is already in
+    /// the wanted state.
+    pub async fn set_checkbox(&self, checkbox: &WebElement, checked: bool) -> Result<(), Error> {
+        if self.get_element_prop::<bool>(checkbox, "checked").await? != checked {
+            self.element_send_keys(checkbox, SPACE).await?;
+        }
+        Ok(())
+    }
+
+    /// Uploads a local file through an `<input type="file">`.
+    pub async fn upload_file<P: AsRef<Path>>(
+        &self,
+        input: &WebElement,
+        path: P,
+    ) -> Result<(), Error> {
+        let path = path.as_ref().canonicalize()?;
+        let path = path
+            .to_str()
+            .ok_or_else(|| failure::format_err!("path is not valid UTF-8"))?;
+        self.element_send_keys(input, path).await
+    }
+}
@@ -0,0 +1,280 @@
+//! Element locators and page objects.
+
+use super::{is_no_such_element, Using, WebDriver, WebElement, WAIT_INTERVAL};
+use failure::Error;
+use std::fmt;
+use std::time::{Duration, Instant};
+use tokio::time::delay_for;
+
+/// Timeout used by page objects unless overridden.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A strategy and selector for finding elements, optionally scoped under
+/// a parent locator.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Locator {
+    using: Using,
+    value: String,
+    parent: Option<Box<Locator>>,
+}
+
+impl Locator {
+    pub fn new<T: Into<String>>(using: Using, value: T) -> Self {
+        Self {
+            using,
+            value: value.into(),
+            parent: None,
+        }
+    }
+
+    pub fn css<T: Into<String>>(value: T) -> Self {
+        Self::new(Using::CssSelector, value)
+    }
+
+    pub fn xpath<T: Into<String>>(value: T) -> Self {
+        Self::new(Using::XPath, value)
+    }
+
+    pub fn link_text<T: Into<String>>(value: T) -> Self {
+        Self::new(Using::LinkText, value)
+    }
+
+    pub fn partial_link_text<T: Into<String>>(value: T) -> Self {
+        Self::new(Using::PartialLinkText, value)
+    }
+
+    pub fn tag_name<T: Into<String>>(value: T) -> Self {
+        Self::new(Using::TagName, value)
+    }
+
+    /// Scopes this locator under the first element matched by `parent`.
+    pub fn within(mut self, parent: Locator) -> Self {
+        self.parent = Some(Box::new(match self.parent.take() {
+            Some(inner) => inner.within(parent),
+            None => parent,
+        }));
+        self
+    }
+
+    /// Returns `child` scoped under this locator.
+    pub fn child(&self, child: Locator) -> Locator {
+        child.within(self.clone())
+    }
+
+    pub fn using(&self) -> Using {
+        self.using
+    }
+
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    pub fn parent(&self) -> Option<&Locator> {
+        self.parent.as_deref()
+    }
+}
+
+impl fmt::Display for Locator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(parent) = &self.parent {
+            write!(f, "{} > ", parent)?;
+        }
+        write!(f, "{:?} {:?}", self.using, self.value)
+    }
+}
+
+impl WebDriver {
+    async fn find_parent(&self, locator: &Locator) -> Result<Option<WebElement>, Error> {
+        match &locator.parent {
+            Some(parent) => Ok(Some(self.find_boxed(parent).await?)),
+            None => Ok(None),
+        }
+    }
+
+    // Recursion in async fns needs an explicit box.
+    fn find_boxed<'a>(
+        &'a self,
+        locator: &'a Locator,
+    ) -> std::pin::Pin<Box<dyn std::future::Future<Output = Result<WebElement, Error>> + Send + 'a>>
+    {
+        Box::pin(self.find(locator))
+    }
+
+    /// Finds the first element matching `locator`.
+    pub async fn find(&self, locator: &Locator) -> Result<WebElement, Error> {
+        match self.find_parent(locator).await? {
+            Some(parent) => {
+                self.get_element_from_element(&parent, locator.using, locator.value.clone())
+                    .await
+            }
+            None => self.get_element(locator.using, locator.value.clone()).await,
+        }
+    }
+
+    /// Finds all elements matching `locator`.
+    pub async fn find_all(&self, locator: &Locator) -> Result<Vec<WebElement>, Error> {
+        match self.find_parent(locator).await? {
+            Some(parent) => {
+                self.get_elements_from_element(&parent, locator.using, locator.value.clone())
+                    .await
+            }
+            None => {
+                self.get_elements(locator.using, locator.value.clone())
+                    .await
+            }
+        }
+    }
+
+    /// Finds `locator` relative to `element`, ignoring the locator's parents.
+    pub async fn find_in(
+        &self,
+        element: &WebElement,
+        locator: &Locator,
+    ) -> Result<WebElement, Error> {
+        self.get_element_from_element(element, locator.using, locator.value.clone())
+            .await
+    }
+
+    /// Finds all matches of `locator` relative to `element`, ignoring the
+    /// locator's parents.
+    pub async fn find_all_in(
+        &self,
+        element: &WebElement,
+        locator: &Locator,
+    ) -> Result<Vec<WebElement>, Error> {
+        self.get_elements_from_element(element, locator.using, locator.value.clone())
+            .await
+    }
+
+    /// Polls for `locator` until it is found or `timeout` has elapsed.
+    pub async fn wait_for(
+        &self,
+        locator: &Locator,
+        timeout: Duration,
+    ) -> Result<WebElement, Error> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            match self.find(locator).await {
+                Err(e) if is_no_such_element(&e) && Instant::now() < deadline => {
+                    delay_for(WAIT_INTERVAL).await;
+                }
+                ret => return ret,
+            }
+        }
+    }
+
+    /// Polls until `locator` matches at least one element or `timeout` has
+    /// elapsed.
+    pub async fn wait_for_all(
+        &self,
+        locator: &Locator,
+        timeout: Duration,
+    ) -> Result<Vec<WebElement>, Error> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            match self.find_all(locator).await {
+                Ok(elements) if elements.is_empty() && Instant::now() < deadline => {}
+                Err(e) if is_no_such_element(&e) && Instant::now() < deadline => {}
+                ret => return ret,
+            }
+            delay_for(WAIT_INTERVAL).await;
+        }
+    }
+}
+
+/// Declares a page object with typed element accessors.
+///
+/// Fields written as `name: locator` get an accessor returning the first
+/// match, fields written as `name: [locator]` one returning all matches.
+/// Accessors wait up to the page's timeout. Each field also gets a
+/// `name_locator()` function returning its [`Locator`].
+///
+/// ```ignore
+/// page_object! {
+///     pub struct LoginPage {
+///         username: Locator::css("#UserName"),
+///         errors: [Locator::css(".error")],
+///     }
+/// }
+///
+/// let page = LoginPage::new(&wd);
+/// let username = page.username().await?;
+/// ```
+#[macro_export]
+macro_rules! page_object {
+    (
+        $(#[$meta:meta])*
+        $vis:vis struct $name:ident {
+            $($fields:tt)*
+        }
+    ) => {
+        $(#[$meta])*
+        $vis struct $name<'a> {
+            driver: &'a $crate::webdriver::WebDriver,
+            timeout: ::std::time::Duration,
+        }
+
+        #[allow(dead_code)]
+        impl<'a> $name<'a> {
+            $vis fn new(driver: &'a $crate::webdriver::WebDriver) -> Self {
+                Self {
+                    driver,
+                    timeout: $crate::webdriver::locator::DEFAULT_TIMEOUT,
+                }
+            }
+
+            /// Sets how long accessors wait for elements to appear.
+            $vis fn with_timeout(mut self, timeout: ::std::time::Duration) -> Self {
+                self.timeout = timeout;
+                self
+            }
+
+            $vis fn driver(&self) -> &'a $crate::webdriver::WebDriver {
+                self.driver
+            }
+        }
+
+        $crate::page_object!(@fields $vis $name $($fields)*);
+    };
+    (@fields $vis:vis $name:ident) => {};
+    (@fields $vis:vis $name:ident $(#[$meta:meta])* $field:ident : [$locator:expr] $(, $($rest:tt)*)?) => {
+        $crate::paste::item! {
+            #[allow(dead_code)]
+            impl<'a> $name<'a> {
+                $vis fn [<$field _locator>]() -> $crate::webdriver::locator::Locator {
+                    $locator
+                }
+
+                $(#[$meta])*
+                $vis async fn $field(
+                    &self,
+                ) -> Result<::std::vec::Vec<$crate::webdriver::WebElement>, ::failure::Error> {
+                    self.driver
+                        .wait_for_all(&Self::[<$field _locator>](), self.timeout)
+                        .await
+                }
+            }
+        }
+        $crate::page_object!(@fields $vis $name $($($rest)*)?);
+    };
+    (@fields $vis:vis $name:ident $(#[$meta:meta])* $field:ident : $locator:expr $(, $($rest:tt)*)?) => {
+        $crate::paste::item! {
+            #[allow(dead_code)]
+            impl<'a> $name<'a> {
+                $vis fn [<$field _locator>]() -> $crate::webdriver::locator::Locator {
+                    $locator
+                }
+
+                $(#[$meta])*
+                $vis async fn $field(
+                    &self,
+                ) -> Result<$crate::webdriver::WebElement, ::failure::Error> {
+                    self.driver
+                        .wait_for(&Self::[<$field _locator>](), self.timeout)
+                        .await
+                }
+            }
+        }
+        $crate::page_object!(@fields $vis $name $($($rest)*)?);
+    };
+}
@@ -0,0 +1,135 @@
+//! Pool of reusable browser sessions.
+
+use super::{CapabilitiesBuilder, WebDriver};
+use failure::Error;
+use log::warn;
+use std::ops::{Deref, DerefMut};
+use std::sync::Mutex;
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+/// A pool of at most `size` Firefox sessions.
+///
+/// Driver processes and sessions are launched lazily when no idle session
+/// is available. Idle sessions are reset before being handed out again,
+/// and sessions that fail to reset are discarded.
+pub struct WebDriverPool {
+    command: Option<String>,
+    caps: CapabilitiesBuilder,
+    verbose: bool,
+    size: usize,
+    idle: Mutex<Vec<WebDriver>>,
+    permits: Semaphore,
+}
+
+impl WebDriverPool {
+    pub fn new_firefox<T>(
+        size: usize,
+        command: Option<T>,
+        caps: CapabilitiesBuilder,
+        verbose: bool,
+    ) -> Self
+    where
+        T: Into<String>,
+    {
+        Self {
+            command: command.map(|x| x.into()),
+            caps,
+            verbose,
+            size,
+            idle: Mutex::new(Vec::new()),
+            permits: Semaphore::new(size),
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Number of sessions waiting to be reused.
+    pub fn idle(&self) -> usize {
+        self.idle.lock().unwrap().len()
+    }
+
+    /// Takes a session from the pool, waiting until one is available.
+    pub async fn get(&self) -> Result<PooledWebDriver<'_>, Error> {
+        let permit = self.permits.acquire().await;
+        loop {
+            let wd = match self.idle.lock().unwrap().pop() {
+                Some(wd) => wd,
+                None => break,
+            };
+            match reset(&wd).await {
+                Ok(()) => return Ok(self.guard(wd, permit)),
+                Err(e) => warn!("discarding dead webdriver session: {}", e),
+            }
+        }
+        let wd = WebDriver::new_firefox_with_caps(
+            self.command.as_ref(),
+            self.caps.clone(),
+            self.verbose,
+        )
+        .await?;
+        Ok(self.guard(wd, permit))
+    }
+
+    /// Closes all idle sessions.
+    pub async fn close(self) -> Result<(), Error> {
+        let idle = self.idle.into_inner().unwrap();
+        for wd in idle {
+            wd.close().await?;
+        }
+        Ok(())
+    }
+
+    fn guard<'a>(&'a self, wd: WebDriver, permit: SemaphorePermit<'a>) -> PooledWebDriver<'a> {
+        PooledWebDriver {
+            pool: self,
+            driver: Some(wd),
+            _permit: permit,
+        }
+    }
+}
+
+async fn reset(wd: &WebDriver) -> Result<(), Error> {
+    wd.delete_all_cookies().await?;
+    wd.navigate("about:blank").await?;
+    Ok(())
+}
+
+/// A session borrowed from a [`WebDriverPool`].
+///
+/// The session is returned to the pool when dropped.
+pub struct PooledWebDriver<'a> {
+    pool: &'a WebDriverPool,
+    driver: Option<WebDriver>,
+    _permit: SemaphorePermit<'a>,
+}
+
+impl PooledWebDriver<'_> {
+    /// Removes the session from the pool instead of returning it.
+    pub fn discard(mut self) -> WebDriver {
+        self.driver.take().unwrap()
+    }
+}
+
+impl Deref for PooledWebDriver<'_> {
+    type Target = WebDriver;
+
+    fn deref(&self) -> &WebDriver {
+        self.driver.as_ref().unwrap()
+    }
+}
+
+impl DerefMut for PooledWebDriver<'_> {
+    fn deref_mut(&mut self) -> &mut WebDriver {
+        self.driver.as_mut().unwrap()
+    }
+}
+
+impl Drop for PooledWebDriver<'_> {
+    fn drop(&mut self) {
+        if let Some(wd) = self.driver.take() {
+            self.pool.idle.lock().unwrap().push(wd);
+        }
+    }
+}
@@ -0,0 +1,1264 @@
+//! Local HTTP proxy that records traffic as HAR.
+//!
+//! HTTP and HTTPS requests are forwarded and recorded with their bodies.
+//! Responses are streamed to the browser as they arrive, and only the
+//! first 16 MiB of each body is recorded.
+//! HTTPS is decrypted with a self-signed certificate generated for each
+//! host, so the session must accept insecure certificates. Pass
+//! [`RecordingProxy::host_port`] to
+//! [`CapabilitiesBuilder::proxy`](super::CapabilitiesBuilder::proxy) and
+//! enable
+//! [`CapabilitiesBuilder::accept_insecure_certs`](super::CapabilitiesBuilder::accept_insecure_certs)
+//! when creating the session.
+
+mod cert;
+
+use super::glob::glob_match;
+use cert::Certs;
+use chrono::Utc;
+use failure::{Error, Fail};
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::prelude::*;
+use tokio::sync::oneshot;
+use tokio_rustls::rustls::ClientConfig;
+use tokio_rustls::webpki::DNSNameRef;
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+/// Largest message head accepted from the browser or upstream.
+const MAX_HEAD_SIZE: usize = 64 * 1024;
+
+/// Most headers accepted in a message head.
+const MAX_HEADERS: usize = 128;
+
+/// Most bytes of each response body kept in the recording. Bodies are
+/// streamed to the browser in full.
+const MAX_RECORDED_BODY: usize = 16 * 1024 * 1024;
+
+/// Headers that apply to a single connection and are not forwarded.
+/// `Accept-Encoding` is replaced so recorded bodies are readable, and
+/// `Expect` is dropped since request bodies are read in full first.
+const SKIPPED_HEADERS: &[&str] = &[
+    "accept-encoding",
+    "connection",
+    "expect",
+    "keep-alive",
+    "proxy-authorization",
+    "proxy-connection",
+    "te",
+    "trailer",
+    "upgrade",
+];
+
+/// A running recording proxy, stopped when dropped.
+pub struct RecordingProxy {
+    addr: SocketAddr,
+    ctx: Arc<Context>,
+    shutdown: Option<oneshot::Sender<()>>,
+}
+
+/// State shared by the proxy connections.
+struct Context {
+    recorder: Recorder,
+    certs: Certs,
+    tls: TlsConnector,
+}
+
+struct Recorder {
+    filters: Vec<String>,
+    entries: Mutex<Vec<HarEntry>>,
+}
+
+impl Recorder {
+    fn record(&self, entry: HarEntry) {
+        let url = &entry.request.url;
+        if self.filters.is_empty() || self.filters.iter().any(|f| glob_match(f, url)) {
+            self.entries.lock().unwrap().push(entry);
+        }
+    }
+}
+
+impl RecordingProxy {
+    /// Starts a proxy on a random local port that records every request.
+    pub async fn start() -> Result<Self, Error> {
+        Self::with_filters(Vec::<String>::new()).await
+    }
+
+    /// Starts a proxy that only records requests whose URL matches one of
+    /// `patterns`, see [`glob_match`].
+    pub async fn with_filters<I, T>(patterns: I) -> Result<Self, Error>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<String>,
+    {
+        let mut listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0))).await?;
+        let addr = listener.local_addr()?;
+        let mut tls = ClientConfig::new();
+        tls.root_store
+            .add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS);
+        tls.set_protocols(&[b"http/1.1".to_vec()]);
+        let ctx = Arc::new(Context {
+            recorder: Recorder {
+                filters: patterns.into_iter().map(|x| x.into()).collect(),
+                entries: Mutex::new(Vec::new()),
+            },
+            certs: Certs::new(),
+            tls: TlsConnector::from(Arc::new(tls)),
+        });
+        let (tx, mut rx) = oneshot::channel();
+        let conn_ctx = ctx.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    conn = listener.accept() => match conn {
+                        Ok((stream, _)) => {
+                            let ctx = conn_ctx.clone();
+                            tokio::spawn(async move {
+                                if let Err(e) = handle(stream, &ctx).await {
+                                    debug!("proxy connection failed: {}", e);
+                                }
+                            });
+                        }
+                        Err(e) => debug!("proxy accept failed: {}", e),
+                    },
+                    _ = &mut rx => break,
+                }
+            }
+        });
+        Ok(Self {
+            addr,
+            ctx,
+            shutdown: Some(tx),
+        })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Returns the address in the `host:port` form used by the `proxy`
+    /// capability.
+    pub fn host_port(&self) -> String {
+        self.addr.to_string()
+    }
+
+    /// Returns the entries recorded so far.
+    pub fn entries(&self) -> Vec<HarEntry> {
+        self.ctx.recorder.entries.lock().unwrap().clone()
+    }
+
+    /// Returns the recorded entries whose URL matches `pattern`.
+    pub fn entries_matching(&self, pattern: &str) -> Vec<HarEntry> {
+        self.ctx
+            .recorder
+            .entries
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|e| glob_match(pattern, &e.request.url))
+            .cloned()
+            .collect()
+    }
+
+    /// Discards the recorded entries.
+    pub fn clear(&self) {
+        self.ctx.recorder.entries.lock().unwrap().clear();
+    }
+
+    /// Returns the recorded entries as a HAR document.
+    pub fn har(&self) -> Har {
+        Har {
+            log: HarLog {
+                version: String::from("1.2"),
+                creator: HarCreator {
+                    name: String::from(env!("CARGO_PKG_NAME")),
+                    version: String::from(env!("CARGO_PKG_VERSION")),
+                },
+                entries: self.entries(),
+            },
+        }
+    }
+
+    /// Writes the recorded entries to `path` as a HAR file.
+    pub fn save_har(&self, path: &Path) -> Result<(), Error> {
+        serde_json::to_writer_pretty(File::create(path)?, &self.har())?;
+        Ok(())
+    }
+}
+
+impl Drop for RecordingProxy {
+    fn drop(&mut self) {
+        if let Some(tx) = self.shutdown.take() {
+            let _ = tx.send(());
+        }
+    }
+}
+
+/// A request read from the browser, with its body decoded.
+struct Request {
+    method: String,
+    target: String,
+    /// Minor HTTP version, `0` or `1`
+    version: u8,
+    headers: Vec<(String, String)>,
+    head_size: usize,
+    /// Whether the request had a `Content-Length` or chunked body
+    has_body: bool,
+    body: Vec<u8>,
+    keep_alive: bool,
+}
+
+/// The end of a message head could not be found within
+/// [`MAX_HEAD_SIZE`] bytes.
+#[derive(Debug, Fail)]
+#[fail(display = "message head too large")]
+struct HeadTooLarge;
+
+/// A connection with the bytes read past the last message.
+struct Conn<S> {
+    stream: S,
+    buf: Vec<u8>,
+}
+
+impl<S: AsyncRead + Unpin> Conn<S> {
+    fn new(stream: S) -> Self {
+        Self {
+            stream,
+            buf: Vec::new(),
+        }
+    }
+
+    /// Reads more data into the buffer, returning the number of bytes read.
+    async fn fill(&mut self) -> Result<usize, Error> {
+        let mut chunk = [0; 8192];
+        let n = self.stream.read(&mut chunk).await?;
+        self.buf.extend_from_slice(&chunk[..n]);
+        Ok(n)
+    }
+
+    /// Reads up to the end of a message head and returns its length, or
+    /// `None` if the connection was closed before any data arrived.
+    async fn read_head(&mut self) -> Result<Option<usize>, Error> {
+        loop {
+            if let Some(pos) = self.buf.windows(4).position(|w| w == b"\r\n\r\n") {
+                return Ok(Some(pos + 4));
+            }
+            if self.buf.len() > MAX_HEAD_SIZE {
+                return Err(HeadTooLarge.into());
+            }
+            if self.fill().await? == 0 {
+                if self.buf.is_empty() {
+                    return Ok(None);
+                }
+                failure::bail!("connection closed before end of message head");
+            }
+        }
+    }
+
+    /// Reads a line and returns it without the `\r\n`.
+    async fn read_line(&mut self) -> Result<Vec<u8>, Error> {
+        loop {
+            if let Some(pos) = self.buf.windows(2).position(|w| w == b"\r\n") {
+                let line = self.buf[..pos].to_vec();
+                self.buf.drain(..pos + 2);
+                return Ok(line);
+            }
+            if self.buf.len() > MAX_HEAD_SIZE {
+                return Err(HeadTooLarge.into());
+            }
+            if self.fill().await? == 0 {
+                failure::bail!("connection closed before end of line");
+            }
+        }
+    }
+
+    /// Returns up to `max` bytes, reading more if none are buffered. An
+    /// empty result means the connection was closed.
+    async fn read_some(&mut self, max: usize) -> Result<Vec<u8>, Error> {
+        if self.buf.is_empty() && self.fill().await? == 0 {
+            return Ok(Vec::new());
+        }
+        let n = max.min(self.buf.len());
+        Ok(self.buf.drain(..n).collect())
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Conn<S> {
+    /// Reads the next request, or `None` if the browser closed the
+    /// connection between requests.
+    ///
+    /// Requests whose head cannot be parsed are answered with an error
+    /// status before failing.
+    async fn read_request(&mut self) -> Result<Option<Request>, Error> {
+        let head_size = match self.read_head().await {
+            Ok(Some(len)) => len,
+            Ok(None) => return Ok(None),
+            Err(e) => {
+                if e.downcast_ref::<HeadTooLarge>().is_some() {
+                    reject(&mut self.stream, "431 Request Header Fields Too Large").await;
+                }
+                return Err(e);
+            }
+        };
+        let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+        let mut req = httparse::Request::new(&mut headers);
+        match req.parse(&self.buf[..head_size]) {
+            Ok(httparse::Status::Complete(_)) => {}
+            Err(httparse::Error::TooManyHeaders) => {
+                reject(&mut self.stream, "431 Request Header Fields Too Large").await;
+                failure::bail!("too many request headers");
+            }
+            _ => {
+                reject(&mut self.stream, "400 Bad Request").await;
+                failure::bail!("invalid request head");
+            }
+        }
+        let method = req.method.unwrap_or("GET").to_owned();
+        let target = req.path.unwrap_or("/").to_owned();
+        let version = req.version.unwrap_or(1);
+        let headers = parse_headers(req.headers);
+        self.buf.drain(..head_size);
+
+        let close = matches!(
+            header(&headers, "connection"),
+            Some(v) if v.to_lowercase().contains("close")
+        );
+        let chunked = matches!(
+            header(&headers, "transfer-encoding"),
+            Some(v) if v.to_lowercase().contains("chunked")
+        );
+        let content_length = match header(&headers, "content-length")
+            .map(|v| v.trim().parse::<usize>())
+            .transpose()
+        {
+            Ok(len) => len,
+            Err(e) => {
+                reject(&mut self.stream, "400 Bad Request").await;
+                return Err(e.into());
+            }
+        };
+        let body = if chunked {
+            loop {
+                if let Some((body, len)) = decode_chunked(&self.buf) {
+                    self.buf.drain(..len);
+                    break body;
+                }
+                if self.fill().await? == 0 {
+                    failure::bail!("connection closed while reading request body");
+                }
+            }
+        } else {
+            let len = content_length.unwrap_or(0);
+            while self.buf.len() < len {
+                if self.fill().await? == 0 {
+                    failure::bail!("connection closed while reading request body");
+                }
+            }
+            self.buf.drain(..len).collect()
+        };
+        Ok(Some(Request {
+            method,
+            target,
+            version,
+            headers,
+            head_size,
+            has_body: chunked || content_length.is_some(),
+            body,
+            keep_alive: version == 1 && !close,
+        }))
+    }
+}
+
+/// Serves requests on a browser connection until either side closes it.
+async fn handle(client: TcpStream, ctx: &Context) -> Result<(), Error> {
+    let mut conn = Conn::new(client);
+    while let Some(req) = conn.read_request().await? {
+        if req.method == "CONNECT" {
+            return intercept(conn, &req.target, ctx).await;
+        }
+        if !forward(&mut conn, req, None, ctx).await? {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Accepts TLS on a `CONNECT` tunnel to `authority` with a generated
+/// certificate and forwards the decrypted requests.
+async fn intercept(conn: Conn<TcpStream>, authority: &str, ctx: &Context) -> Result<(), Error> {
+    let Conn { mut stream, buf } = conn;
+    if !buf.is_empty() {
+        failure::bail!("unexpected data after CONNECT request");
+    }
+    let (host, _) = split_authority(authority, 443);
+    let config = ctx.certs.server_config(host)?;
+    stream
+        .write_all(b"HTTP/1.1 200 Connection Established\r\n\r\n")
+        .await?;
+    let stream = TlsAcceptor::from(config).accept(stream).await?;
+    let mut conn = Conn::new(stream);
+    while let Some(req) = conn.read_request().await? {
+        if !forward(&mut conn, req, Some(authority), ctx).await? {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Forwards a request upstream, streams the response to the browser and
+/// records it. `authority` is the `CONNECT` target for HTTPS requests.
+///
+/// Returns whether the browser connection can be reused.
+async fn forward<S: AsyncRead + AsyncWrite + Unpin>(
+    conn: &mut Conn<S>,
+    req: Request,
+    authority: Option<&str>,
+    ctx: &Context,
+) -> Result<bool, Error> {
+    let started = Utc::now();
+    let start = Instant::now();
+    let (host, path, url) = match authority {
+        Some(authority) => {
+            let url = match authority.strip_suffix(":443") {
+                Some(host) => format!("https://{}{}", host, req.target),
+                None => format!("https://{}{}", authority, req.target),
+            };
+            (authority.to_owned(), req.target.clone(), url)
+        }
+        None => match split_url(&req.target) {
+            Ok((host, path)) => (host, path, req.target.clone()),
+            Err(e) => {
+                reject(&mut conn.stream, "400 Bad Request").await;
+                return Err(e);
+            }
+        },
+    };
+    let forwarded = upstream_request(&req, &path);
+
+    let exchange = match authority {
+        Some(_) => match connect_tls(&host, ctx).await {
+            Ok(upstream) => relay(conn, &req, upstream, &forwarded).await?,
+            Err(e) => {
+                reject(&mut conn.stream, "502 Bad Gateway").await;
+                return Err(e);
+            }
+        },
+        None => match TcpStream::connect(&host).await {
+            Ok(upstream) => relay(conn, &req, upstream, &forwarded).await?,
+            Err(e) => {
+                reject(&mut conn.stream, "502 Bad Gateway").await;
+                return Err(e.into());
+            }
+        },
+    };
+
+    let body = &exchange.body;
+    let mime_type = header(&exchange.headers, "content-type")
+        .unwrap_or_default()
+        .to_owned();
+    let (text, encoding) = match String::from_utf8(body.clone()) {
+        Ok(text) => (text, None),
+        Err(_) => (base64::encode(body), Some(String::from("base64"))),
+    };
+    let comment = if body.len() < exchange.body_size {
+        Some(format!("body truncated to {} bytes", body.len()))
+    } else {
+        None
+    };
+    let mut request = har_request(&req.method, url, &req.headers, &req.body);
+    request.headers_size = req.head_size as i64;
+    ctx.recorder.record(HarEntry {
+        started_date_time: started.to_rfc3339(),
+        time: millis(exchange.done - start),
+        request,
+        response: HarResponse {
+            status: exchange.status,
+            status_text: exchange.reason.clone(),
+            http_version: format!("HTTP/1.{}", exchange.version),
+            cookies: Vec::new(),
+            redirect_url: header(&exchange.headers, "location")
+                .unwrap_or_default()
+                .to_owned(),
+            headers: har_headers(&exchange.headers),
+            content: HarContent {
+                size: exchange.body_size as i64,
+                mime_type,
+                text: Some(text),
+                encoding,
+                comment,
+            },
+            headers_size: exchange.head_size as i64,
+            body_size: exchange.body_size as i64,
+        },
+        cache: HarCache {},
+        timings: HarTimings {
+            send: millis(exchange.sent - start),
+            wait: millis(exchange.first_byte - exchange.sent),
+            receive: millis(exchange.done - exchange.first_byte),
+        },
+    });
+    Ok(req.keep_alive)
+}
+
+/// Builds the request sent upstream for `req`, with `path` as the target.
+fn upstream_request(req: &Request, path: &str) -> Vec<u8> {
+    let mut forwarded = format!("{} {} HTTP/1.1\r\n", req.method, path);
+    for (name, value) in &req.headers {
+        let name_lower = name.to_lowercase();
+        if !SKIPPED_HEADERS.contains(&name_lower.as_str())
+            && name_lower != "content-length"
+            && name_lower != "transfer-encoding"
+        {
+            forwarded.push_str(&format!("{}: {}\r\n", name, value));
+        }
+    }
+    if req.has_body {
+        forwarded.push_str(&format!("Content-Length: {}\r\n", req.body.len()));
+    }
+    forwarded.push_str("Accept-Encoding: identity\r\nConnection: close\r\n\r\n");
+    let mut forwarded = forwarded.into_bytes();
+    forwarded.extend_from_slice(&req.body);
+    forwarded
+}
+
+async fn connect_tls(
+    authority: &str,
+    ctx: &Context,
+) -> Result<tokio_rustls::client::TlsStream<TcpStream>, Error> {
+    let (name, _) = split_authority(authority, 443);
+    let name = DNSNameRef::try_from_ascii_str(name)
+        .map_err(|_| failure::format_err!("invalid TLS server name {:?}", name))?;
+    let upstream = TcpStream::connect(authority).await?;
+    Ok(ctx.tls.connect(name, upstream).await?)
+}
+
+/// An upstream response that was sent to the browser, with the time of
+/// each phase.
+struct Exchange {
+    status: u16,
+    reason: String,
+    version: u8,
+    headers: Vec<(String, String)>,
+    head_size: usize,
+    /// The start of the decoded body, at most [`MAX_RECORDED_BODY`] bytes
+    body: Vec<u8>,
+    /// Size of the whole decoded body
+    body_size: usize,
+    sent: Instant,
+    first_byte: Instant,
+    done: Instant,
+}
+
+/// How the end of a response body is found.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Framing {
+    Empty,
+    Length(usize),
+    Chunked,
+    Close,
+}
+
+/// Sends `forwarded` on a new upstream connection and streams the response
+/// to the browser as it arrives.
+///
+/// Failures before the response head is sent to the browser are answered
+/// with 502 Bad Gateway.
+async fn relay<S, U>(
+    conn: &mut Conn<S>,
+    req: &Request,
+    upstream: U,
+    forwarded: &[u8],
+) -> Result<Exchange, Error>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    U: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut upstream = Conn::new(upstream);
+    let head = async {
+        upstream.stream.write_all(forwarded).await?;
+        upstream.stream.flush().await?;
+        let sent = Instant::now();
+        let head_size = upstream
+            .read_head()
+            .await?
+            .ok_or_else(|| failure::format_err!("empty upstream response"))?;
+        Ok::<_, Error>((sent, head_size))
+    };
+    let (sent, head_size) = match head.await {
+        Ok(head) => head,
+        Err(e) => {
+            reject(&mut conn.stream, "502 Bad Gateway").await;
+            return Err(e);
+        }
+    };
+    let first_byte = Instant::now();
+
+    let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+    let mut resp = httparse::Response::new(&mut headers);
+    if !matches!(
+        resp.parse(&upstream.buf[..head_size]),
+        Ok(httparse::Status::Complete(_))
+    ) {
+        reject(&mut conn.stream, "502 Bad Gateway").await;
+        failure::bail!("invalid upstream response head");
+    }
+    let status = resp.code.unwrap_or(0);
+    let reason = resp.reason.unwrap_or_default().to_owned();
+    let version = resp.version.unwrap_or(1);
+    let headers = parse_headers(resp.headers);
+    upstream.buf.drain(..head_size);
+
+    let bodiless = req.method == "HEAD" || status < 200 || status == 204 || status == 304;
+    let framing = if bodiless {
+        Framing::Empty
+    } else if matches!(
+        header(&headers, "transfer-encoding"),
+        Some(v) if v.to_lowercase().contains("chunked")
+    ) {
+        Framing::Chunked
+    } else if let Some(len) = header(&headers, "content-length") {
+        match len.trim().parse() {
+            Ok(len) => Framing::Length(len),
+            Err(_) => {
+                reject(&mut conn.stream, "502 Bad Gateway").await;
+                failure::bail!("invalid upstream Content-Length {:?}", len);
+            }
+        }
+    } else {
+        Framing::Close
+    };
+    // Bodies of unknown length are re-chunked for HTTP/1.1 browsers and
+    // delimited by closing the connection otherwise.
+    let chunked = req.version == 1 && matches!(framing, Framing::Chunked | Framing::Close);
+
+    let mut head = format!("HTTP/1.1 {} {}\r\n", status, reason);
+    for (name, value) in &headers {
+        let name_lower = name.to_lowercase();
+        let framing_header = name_lower == "content-length" || name_lower == "transfer-encoding";
+        if !SKIPPED_HEADERS.contains(&name_lower.as_str()) && (bodiless || !framing_header) {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+    }
+    if let Framing::Length(len) = framing {
+        head.push_str(&format!("Content-Length: {}\r\n", len));
+    } else if chunked {
+        head.push_str("Transfer-Encoding: chunked\r\n");
+    }
+    head.push_str(if req.keep_alive {
+        "Connection: keep-alive\r\n\r\n"
+    } else {
+        "Connection: close\r\n\r\n"
+    });
+    conn.stream.write_all(head.as_bytes()).await?;
+    conn.stream.flush().await?;
+
+    let mut body = BodyWriter {
+        stream: &mut conn.stream,
+        chunked,
+        recorded: Vec::new(),
+        size: 0,
+    };
+    match framing {
+        Framing::Empty => {}
+        Framing::Length(len) => {
+            let mut left = len;
+            while left > 0 {
+                let data = upstream.read_some(left).await?;
+                if data.is_empty() {
+                    failure::bail!("upstream closed before end of response body");
+                }
+                left -= data.len();
+                body.write(&data).await?;
+            }
+        }
+        Framing::Chunked => loop {
+            let line = upstream.read_line().await?;
+            let size = chunk_size(&line)
+                .ok_or_else(|| failure::format_err!("invalid upstream chunk size"))?;
+            if size == 0 {
+                // Skip trailers up to the final empty line.
+                while !upstream.read_line().await?.is_empty() {}
+                break;
+            }
+            let mut left = size;
+            while left > 0 {
+                let data = upstream.read_some(left).await?;
+                if data.is_empty() {
+                    failure::bail!("upstream closed before end of response body");
+                }
+                left -= data.len();
+                body.write(&data).await?;
+            }
+            if !upstream.read_line().await?.is_empty() {
+                failure::bail!("invalid upstream chunk");
+            }
+        },
+        Framing::Close => loop {
+            let data = upstream.read_some(usize::MAX).await?;
+            if data.is_empty() {
+                break;
+            }
+            body.write(&data).await?;
+        },
+    }
+    let (recorded, body_size) = body.finish().await?;
+    if !req.keep_alive {
+        conn.stream.shutdown().await?;
+    }
+    Ok(Exchange {
+        status,
+        reason,
+        version,
+        headers,
+        head_size,
+        body: recorded,
+        body_size,
+        sent,
+        first_byte,
+        done: Instant::now(),
+    })
+}
+
+/// Writes a response body to the browser while keeping its start for the
+/// recording.
+struct BodyWriter<'a, S> {
+    stream: &'a mut S,
+    chunked: bool,
+    recorded: Vec<u8>,
+    size: usize,
+}
+
+impl<'a, S: AsyncWrite + Unpin> BodyWriter<'a, S> {
+    async fn write(&mut self, data: &[u8]) -> Result<(), Error> {
+        if data.is_empty() {
+            return Ok(());
+        }
+        if self.chunked {
+            let size = format!("{:x}\r\n", data.len());
+            self.stream.write_all(size.as_bytes()).await?;
+            self.stream.write_all(data).await?;
+            self.stream.write_all(b"\r\n").await?;
+        } else {
+            self.stream.write_all(data).await?;
+        }
+        self.stream.flush().await?;
+        let room = MAX_RECORDED_BODY.saturating_sub(self.recorded.len());
+        self.recorded
+            .extend_from_slice(&data[..data.len().min(room)]);
+        self.size += data.len();
+        Ok(())
+    }
+
+    /// Ends the body, returning the recorded part and the full size.
+    async fn finish(self) -> Result<(Vec<u8>, usize), Error> {
+        if self.chunked {
+            self.stream.write_all(b"0\r\n\r\n").await?;
+            self.stream.flush().await?;
+        }
+        Ok((self.recorded, self.size))
+    }
+}
+
+/// Answers the browser with an empty `status` response and closes the
+/// connection.
+async fn reject<S: AsyncWrite + Unpin>(stream: &mut S, status: &str) {
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        status
+    );
+    let _ = stream.write_all(response.as_bytes()).await;
+    let _ = stream.shutdown().await;
+}
+
+fn parse_headers(headers: &[httparse::Header]) -> Vec<(String, String)> {
+    headers
+        .iter()
+        .map(|h| {
+            (
+                h.name.to_owned(),
+                String::from_utf8_lossy(h.value).into_owned(),
+            )
+        })
+        .collect()
+}
+
+/// Splits `host:port`, using `default_port` if there is no port.
+fn split_authority(authority: &str, default_port: u16) -> (&str, u16) {
+    match authority.rfind(':') {
+        Some(idx) if !authority[idx..].contains(']') => (
+            authority[..idx]
+                .trim_start_matches('[')
+                .trim_end_matches(']'),
+            authority[idx + 1..].parse().unwrap_or(default_port),
+        ),
+        _ => (
+            authority.trim_start_matches('[').trim_end_matches(']'),
+            default_port,
+        ),
+    }
+}
+
+/// Splits an absolute `http://` URL into `host:port` and the path.
+fn split_url(url: &str) -> Result<(String, String), Error> {
+    let rest = match url.strip_prefix("http://") {
+        Some(rest) => rest,
+        None => failure::bail!("unsupported proxy request target {:?}", url),
+    };
+    let (host, path) = match rest.find(&['/', '?'][..]) {
+        Some(idx) if rest[idx..].starts_with('?') => (&rest[..idx], format!("/{}", &rest[idx..])),
+        Some(idx) => (&rest[..idx], rest[idx..].to_owned()),
+        None => (rest, String::from("/")),
+    };
+    if host.is_empty() {
+        failure::bail!("missing host in proxy request target {:?}", url);
+    }
+    // IPv6 addresses are bracketed, so a port follows the last `]`.
+    let has_port = matches!(host.rfind(':'), Some(idx) if !host[idx..].contains(']'));
+    let host = if has_port {
+        host.to_owned()
+    } else {
+        format!("{}:80", host)
+    };
+    Ok((host, path))
+}
+
+fn header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(n, _)| n.eq_ignore_ascii_case(name))
+        .map(|(_, v)| v.as_str())
+}
+
+/// Parses the size at the start of a chunk, ignoring extensions.
+fn chunk_size(line: &[u8]) -> Option<usize> {
+    let line = std::str::from_utf8(line).ok()?;
+    usize::from_str_radix(line.split(';').next()?.trim(), 16).ok()
+}
+
+/// Decodes a chunked body, returning it with the number of bytes used
+/// including trailers, or `None` if `data` is incomplete.
+fn decode_chunked(data: &[u8]) -> Option<(Vec<u8>, usize)> {
+    let mut body = Vec::new();
+    let mut pos = 0;
+    loop {
+        let line_end = pos + data[pos..].windows(2).position(|w| w == b"\r\n")?;
+        let size = chunk_size(&data[pos..line_end])?;
+        pos = line_end + 2;
+        if size == 0 {
+            break;
+        }
+        body.extend_from_slice(data.get(pos..pos + size)?);
+        pos += size + 2;
+        if data.len() < pos {
+            return None;
+        }
+    }
+    // Skip trailers up to the final empty line.
+    loop {
+        let line_end = pos + data[pos..].windows(2).position(|w| w == b"\r\n")?;
+        let empty = line_end == pos;
+        pos = line_end + 2;
+        if empty {
+            return Some((body, pos));
+        }
+    }
+}
+
+fn millis(duration: std::time::Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+fn har_headers(headers: &[(String, String)]) -> Vec<HarNameValue> {
+    headers
+        .iter()
+        .map(|(name, value)| HarNameValue {
+            name: name.clone(),
+            value: value.clone(),
+        })
+        .collect()
+}
+
+fn har_request(method: &str, url: String, headers: &[(String, String)], body: &[u8]) -> HarRequest {
+    let query_string = match url.find('?') {
+        Some(idx) => url[idx + 1..]
+            .split('&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                let mut parts = pair.splitn(2, '=');
+                HarNameValue {
+                    name: parts.next().unwrap_or_default().to_owned(),
+                    value: parts.next().unwrap_or_default().to_owned(),
+                }
+            })
+            .collect(),
+        None => Vec::new(),
+    };
+    let post_data = if body.is_empty() {
+        None
+    } else {
+        Some(HarPostData {
+            mime_type: header(headers, "content-type")
+                .unwrap_or_default()
+                .to_owned(),
+            text: String::from_utf8_lossy(body).into_owned(),
+        })
+    };
+    HarRequest {
+        method: method.to_owned(),
+        url,
+        http_version: String::from("HTTP/1.1"),
+        cookies: Vec::new(),
+        headers: har_headers(headers),
+        query_string,
+        post_data,
+        headers_size: -1,
+        body_size: body.len() as i64,
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Har {
+    pub log: HarLog,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct HarLog {
+    pub version: String,
+    pub creator: HarCreator,
+    pub entries: Vec<HarEntry>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct HarCreator {
+    pub name: String,
+    pub version: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct HarEntry {
+    pub started_date_time: String,
+    /// Total time in milliseconds
+    pub time: f64,
+    pub request: HarRequest,
+    pub response: HarResponse,
+    pub cache: HarCache,
+    pub timings: HarTimings,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct HarRequest {
+    pub method: String,
+    pub url: String,
+    pub http_version: String,
+    pub cookies: Vec<HarNameValue>,
+    pub headers: Vec<HarNameValue>,
+    pub query_string: Vec<HarNameValue>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub post_data: Option<HarPostData>,
+    pub headers_size: i64,
+    pub body_size: i64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct HarResponse {
+    pub status: u16,
+    pub status_text: String,
+    pub http_version: String,
+    pub cookies: Vec<HarNameValue>,
+    pub headers: Vec<HarNameValue>,
+    pub content: HarContent,
+    #[serde(rename = "redirectURL")]
+    pub redirect_url: String,
+    pub headers_size: i64,
+    pub body_size: i64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct HarNameValue {
+    pub name: String,
+    pub value: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct HarPostData {
+    pub mime_type: String,
+    pub text: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct HarContent {
+    pub size: i64,
+    pub mime_type: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    /// `base64` for binary bodies
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encoding: Option<String>,
+    /// Set when `text` holds only the start of the body
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct HarCache {}
+
+/// Phase durations in milliseconds.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct HarTimings {
+    pub send: f64,
+    pub wait: f64,
+    pub receive: f64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+
+    /// A stream that reads from `input` in `step` sized pieces and
+    /// collects what is written.
+    struct Mock {
+        input: Vec<u8>,
+        step: usize,
+        output: Vec<u8>,
+    }
+
+    impl Mock {
+        fn new(input: &[u8], step: usize) -> Self {
+            Self {
+                input: input.to_vec(),
+                step,
+                output: Vec::new(),
+            }
+        }
+    }
+
+    impl AsyncRead for Mock {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            _: &mut Context,
+            buf: &mut [u8],
+        ) -> Poll<std::io::Result<usize>> {
+            let n = self.step.min(buf.len()).min(self.input.len());
+            buf[..n].copy_from_slice(&self.input[..n]);
+            self.input.drain(..n);
+            Poll::Ready(Ok(n))
+        }
+    }
+
+    impl AsyncWrite for Mock {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            _: &mut Context,
+            buf: &[u8],
+        ) -> Poll<std::io::Result<usize>> {
+            self.output.extend_from_slice(buf);
+            Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _: &mut Context) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    fn read_request(input: &[u8]) -> (Result<Option<Request>, Error>, Vec<u8>) {
+        let mut conn = Conn::new(Mock::new(input, 7));
+        let req = block_on(conn.read_request());
+        (req, conn.stream.output)
+    }
+
+    fn relay_response(request: &[u8], response: &[u8]) -> (Exchange, String) {
+        let req = read_request(request).0.unwrap().unwrap();
+        let mut conn = Conn::new(Mock::new(b"", 1));
+        let exchange = block_on(relay(&mut conn, &req, Mock::new(response, 5), b"")).unwrap();
+        let output = String::from_utf8(conn.stream.output).unwrap();
+        (exchange, output)
+    }
+
+    #[test]
+    fn decode_chunked_bodies() {
+        let data = b"4\r\nWiki\r\n5;ext=1\r\npedia\r\n0\r\nExpires: never\r\n\r\nnext";
+        let (body, used) = decode_chunked(data).unwrap();
+        assert_eq!(body, b"Wikipedia");
+        assert_eq!(&data[used..], b"next");
+
+        assert_eq!(decode_chunked(b"0\r\n\r\n"), Some((Vec::new(), 5)));
+        assert_eq!(decode_chunked(b"4\r\nWik"), None);
+        assert_eq!(decode_chunked(b"4\r\nWiki\r\n0\r\n"), None);
+        assert_eq!(decode_chunked(b"zz\r\n"), None);
+    }
+
+    #[test]
+    fn split_authorities() {
+        assert_eq!(
+            split_authority("example.com:8443", 443),
+            ("example.com", 8443)
+        );
+        assert_eq!(split_authority("example.com", 443), ("example.com", 443));
+        assert_eq!(split_authority("[::1]:8443", 443), ("::1", 8443));
+        assert_eq!(split_authority("[::1]", 443), ("::1", 443));
+    }
+
+    #[test]
+    fn split_urls() {
+        let split = |url| split_url(url).unwrap();
+        assert_eq!(
+            split("http://example.com/a?b=c"),
+            (String::from("example.com:80"), String::from("/a?b=c"))
+        );
+        assert_eq!(
+            split("http://example.com:8080"),
+            (String::from("example.com:8080"), String::from("/"))
+        );
+        assert_eq!(
+            split("http://example.com?q"),
+            (String::from("example.com:80"), String::from("/?q"))
+        );
+        assert_eq!(
+            split("http://[::1]/x"),
+            (String::from("[::1]:80"), String::from("/x"))
+        );
+        assert_eq!(
+            split("http://[::1]:8080/x"),
+            (String::from("[::1]:8080"), String::from("/x"))
+        );
+        assert!(split_url("https://example.com/").is_err());
+        assert!(split_url("http:///x").is_err());
+    }
+
+    #[test]
+    fn har_request_fields() {
+        let headers = vec![(String::from("Content-Type"), String::from("text/plain"))];
+        let req = har_request(
+            "POST",
+            String::from("http://example.com/?a=1&b&&c=x=y"),
+            &headers,
+            b"hello",
+        );
+        assert_eq!(req.method, "POST");
+        let query = req
+            .query_string
+            .iter()
+            .map(|q| (q.name.as_str(), q.value.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(query, vec![("a", "1"), ("b", ""), ("c", "x=y")]);
+        let post = req.post_data.unwrap();
+        assert_eq!(post.mime_type, "text/plain");
+        assert_eq!(post.text, "hello");
+        assert_eq!(req.body_size, 5);
+
+        let req = har_request("GET", String::from("http://example.com/"), &[], b"");
+        assert!(req.query_string.is_empty());
+        assert!(req.post_data.is_none());
+    }
+
+    #[test]
+    fn read_chunked_request() {
+        let (req, _) = read_request(
+            b"POST http://example.com/ HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n0\r\n\r\n",
+        );
+        let req = req.unwrap().unwrap();
+        assert_eq!(req.body, b"abc");
+        assert!(req.keep_alive);
+        let forwarded = String::from_utf8(upstream_request(&req, "/")).unwrap();
+        assert!(forwarded.contains("Content-Length: 3\r\n"));
+        assert!(!forwarded.contains("Transfer-Encoding"));
+        assert!(forwarded.ends_with("\r\n\r\nabc"));
+    }
+
+    #[test]
+    fn reject_too_many_headers() {
+        let mut input = b"GET http://example.com/ HTTP/1.1\r\n".to_vec();
+        for i in 0..=MAX_HEADERS {
+            input.extend_from_slice(format!("X-{}: a\r\n", i).as_bytes());
+        }
+        input.extend_from_slice(b"\r\n");
+        let (req, output) = read_request(&input);
+        assert!(req.is_err());
+        assert!(output.starts_with(b"HTTP/1.1 431 "));
+    }
+
+    #[test]
+    fn reject_large_head() {
+        let mut input = b"GET http://example.com/ HTTP/1.1\r\nX-A: ".to_vec();
+        input.extend_from_slice(&vec![b'a'; MAX_HEAD_SIZE]);
+        let mut conn = Conn::new(Mock::new(&input, 8192));
+        assert!(block_on(conn.read_request()).is_err());
+        assert!(conn.stream.output.starts_with(b"HTTP/1.1 431 "));
+    }
+
+    #[test]
+    fn relay_with_length() {
+        let (exchange, output) = relay_response(
+            b"GET http://example.com/ HTTP/1.1\r\n\r\n",
+            b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\nContent-Type: text/plain\r\n\r\nhello",
+        );
+        assert_eq!(exchange.status, 200);
+        assert_eq!(exchange.body, b"hello");
+        assert_eq!(exchange.body_size, 5);
+        assert!(output.contains("Content-Length: 5\r\n"));
+        assert!(output.ends_with("\r\n\r\nhello"));
+    }
+
+    #[test]
+    fn relay_chunked() {
+        let (exchange, output) = relay_response(
+            b"GET http://example.com/ HTTP/1.1\r\n\r\n",
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n4\r\nWiki\r\n5\r\npedia\r\n0\r\n\r\n",
+        );
+        assert_eq!(exchange.body, b"Wikipedia");
+        assert!(output.contains("Transfer-Encoding: chunked\r\n"));
+        assert!(output.ends_with("0\r\n\r\n"));
+        let body = &output[output.find("\r\n\r\n").unwrap() + 4..];
+        assert_eq!(
+            decode_chunked(body.as_bytes()).unwrap().0,
+            b"Wikipedia".to_vec()
+        );
+    }
+
+    #[test]
+    fn relay_until_close() {
+        let (exchange, output) = relay_response(
+            b"GET http://example.com/ HTTP/1.0\r\n\r\n",
+            b"HTTP/1.0 200 OK\r\n\r\nstreamed body",
+        );
+        assert_eq!(exchange.body, b"streamed body");
+        assert!(!output.contains("Transfer-Encoding"));
+        assert!(output.contains("Connection: close\r\n"));
+        assert!(output.ends_with("\r\n\r\nstreamed body"));
+
+        let (_, output) = relay_response(
+            b"GET http://example.com/ HTTP/1.1\r\n\r\n",
+            b"HTTP/1.0 200 OK\r\n\r\nstreamed body",
+        );
+        assert!(output.contains("Transfer-Encoding: chunked\r\n"));
+    }
+
+    #[test]
+    fn relay_empty_response() {
+        let req = read_request(b"GET http://example.com/ HTTP/1.1\r\n\r\n")
+            .0
+            .unwrap()
+            .unwrap();
+        let mut conn = Conn::new(Mock::new(b"", 1));
+        assert!(block_on(relay(&mut conn, &req, Mock::new(b"", 1), b"")).is_err());
+        assert!(conn.stream.output.starts_with(b"HTTP/1.1 502 "));
+    }
+}
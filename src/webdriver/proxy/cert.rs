@@ -0,0 +1,222 @@
+//! Self-signed certificates for intercepting HTTPS traffic.
+
+use chrono::{Duration, Utc};
+use failure::Error;
+use ring::rand::{SecureRandom, SystemRandom};
+use ring::signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_ASN1_SIGNING};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use tokio_rustls::rustls::{Certificate, NoClientAuth, PrivateKey, ServerConfig};
+
+/// `ecdsa-with-SHA256`
+const OID_ECDSA_SHA256: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x02];
+/// `id-ecPublicKey`
+const OID_EC_PUBLIC_KEY: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01];
+/// `prime256v1`
+const OID_P256: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07];
+/// `commonName`
+const OID_COMMON_NAME: &[u8] = &[0x55, 0x04, 0x03];
+/// `subjectAltName`
+const OID_SUBJECT_ALT_NAME: &[u8] = &[0x55, 0x1d, 0x11];
+
+/// TLS server configurations with a certificate for each intercepted host.
+pub(super) struct Certs {
+    rng: SystemRandom,
+    configs: Mutex<HashMap<String, Arc<ServerConfig>>>,
+}
+
+impl Certs {
+    pub(super) fn new() -> Self {
+        Self {
+            rng: SystemRandom::new(),
+            configs: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the server configuration for `host`, generating a
+    /// certificate on first use.
+    pub(super) fn server_config(&self, host: &str) -> Result<Arc<ServerConfig>, Error> {
+        if let Some(config) = self.configs.lock().unwrap().get(host) {
+            return Ok(config.clone());
+        }
+        let (cert, key) = self.self_signed(host)?;
+        let mut config = ServerConfig::new(NoClientAuth::new());
+        config.set_single_cert(vec![Certificate(cert)], PrivateKey(key))?;
+        config.set_protocols(&[b"http/1.1".to_vec()]);
+        let config = Arc::new(config);
+        self.configs
+            .lock()
+            .unwrap()
+            .insert(host.to_owned(), config.clone());
+        Ok(config)
+    }
+
+    /// Generates a P-256 key and a self-signed certificate for `host`,
+    /// returning both in DER form.
+    fn self_signed(&self, host: &str) -> Result<(Vec<u8>, Vec<u8>), Error> {
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, &self.rng)
+            .map_err(|_| failure::format_err!("unable to generate certificate key"))?;
+        let key = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, pkcs8.as_ref())
+            .map_err(|e| failure::format_err!("unable to load certificate key: {}", e))?;
+        let mut serial = [0; 16];
+        self.rng
+            .fill(&mut serial)
+            .map_err(|_| failure::format_err!("unable to generate certificate serial"))?;
+        // Keep the serial number positive and its first byte non-zero, so
+        // the INTEGER is minimally encoded.
+        serial[0] = (serial[0] & 0x7f) | 0x40;
+
+        let signature_algorithm = der(0x30, &der(0x06, OID_ECDSA_SHA256));
+        let name = der(
+            0x30,
+            &der(
+                0x31,
+                &der(
+                    0x30,
+                    &[der(0x06, OID_COMMON_NAME), der(0x0c, host.as_bytes())].concat(),
+                ),
+            ),
+        );
+        let now = Utc::now();
+        let validity = der(
+            0x30,
+            &[
+                utc_time(now - Duration::days(1)),
+                utc_time(now + Duration::days(365)),
+            ]
+            .concat(),
+        );
+        let public_key = der(
+            0x30,
+            &[
+                der(
+                    0x30,
+                    &[der(0x06, OID_EC_PUBLIC_KEY), der(0x06, OID_P256)].concat(),
+                ),
+                bit_string(key.public_key().as_ref()),
+            ]
+            .concat(),
+        );
+        let alt_name = match host.parse::<IpAddr>() {
+            Ok(IpAddr::V4(ip)) => der(0x87, &ip.octets()),
+            Ok(IpAddr::V6(ip)) => der(0x87, &ip.octets()),
+            Err(_) => der(0x82, host.as_bytes()),
+        };
+        let extensions = der(
+            0xa3,
+            &der(
+                0x30,
+                &der(
+                    0x30,
+                    &[
+                        der(0x06, OID_SUBJECT_ALT_NAME),
+                        der(0x04, &der(0x30, &alt_name)),
+                    ]
+                    .concat(),
+                ),
+            ),
+        );
+        let tbs = der(
+            0x30,
+            &[
+                der(0xa0, &der(0x02, &[2])),
+                der(0x02, &serial),
+                signature_algorithm.clone(),
+                name.clone(),
+                validity,
+                name,
+                public_key,
+                extensions,
+            ]
+            .concat(),
+        );
+        let signature = key
+            .sign(&self.rng, &tbs)
+            .map_err(|_| failure::format_err!("unable to sign certificate"))?;
+        let cert = der(
+            0x30,
+            &[tbs, signature_algorithm, bit_string(signature.as_ref())].concat(),
+        );
+        Ok((cert, pkcs8.as_ref().to_vec()))
+    }
+}
+
+/// Encodes a DER value with the given tag.
+fn der(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut out = vec![tag];
+    let len = content.len();
+    if len < 0x80 {
+        out.push(len as u8);
+    } else {
+        let bytes = len.to_be_bytes();
+        let skip = bytes.iter().take_while(|&&b| b == 0).count();
+        out.push(0x80 | (bytes.len() - skip) as u8);
+        out.extend_from_slice(&bytes[skip..]);
+    }
+    out.extend_from_slice(content);
+    out
+}
+
+/// Encodes a BIT STRING with no unused bits.
+fn bit_string(content: &[u8]) -> Vec<u8> {
+    der(0x03, &[&[0][..], content].concat())
+}
+
+/// Encodes a UTCTime.
+fn utc_time(time: chrono::DateTime<Utc>) -> Vec<u8> {
+    der(0x17, time.format("%y%m%d%H%M%SZ").to_string().as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn der_short_and_long_lengths() {
+        assert_eq!(der(0x04, &[1, 2]), vec![0x04, 2, 1, 2]);
+        assert_eq!(der(0x04, &[]), vec![0x04, 0]);
+
+        let long = der(0x04, &[0; 0x80]);
+        assert_eq!(&long[..3], &[0x04, 0x81, 0x80]);
+        assert_eq!(long.len(), 3 + 0x80);
+
+        let longer = der(0x30, &[0; 0x1234]);
+        assert_eq!(&longer[..4], &[0x30, 0x82, 0x12, 0x34]);
+        assert_eq!(longer.len(), 4 + 0x1234);
+    }
+
+    #[test]
+    fn bit_string_has_no_unused_bits() {
+        assert_eq!(bit_string(&[0xff, 0x01]), vec![0x03, 3, 0, 0xff, 0x01]);
+    }
+
+    #[test]
+    fn utc_time_format() {
+        let time = Utc.ymd(2020, 4, 7).and_hms(9, 5, 3);
+        assert_eq!(utc_time(time), [&[0x17, 13][..], b"200407090503Z"].concat());
+    }
+
+    #[test]
+    fn serial_is_minimally_encoded() {
+        let certs = Certs::new();
+        for _ in 0..32 {
+            let (cert, _) = certs.self_signed("example.com").unwrap();
+            // The serial number follows the `[0] INTEGER 2` version.
+            let version = [0xa0, 3, 0x02, 1, 2];
+            let offset = cert.windows(5).position(|w| w == version).unwrap() + 5;
+            assert_eq!(&cert[offset..offset + 2], &[0x02, 16]);
+            let first = cert[offset + 2];
+            assert!(first & 0x80 == 0 && first != 0);
+        }
+    }
+
+    #[test]
+    fn server_config_is_cached() {
+        let certs = Certs::new();
+        let a = certs.server_config("127.0.0.1").unwrap();
+        let b = certs.server_config("127.0.0.1").unwrap();
+        assert!(Arc::ptr_eq(&a, &b));
+    }
+}
@@ -0,0 +1,236 @@
+//! Screenshot comparison against stored baselines.
+
+use super::WebDriver;
+use failure::Error;
+use png::{BitDepth, ColorType, Decoder, Encoder, Transformations};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// A rectangle in image pixels.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Region {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Region {
+    fn contains(&self, x: u32, y: u32) -> bool {
+        x >= self.x && x - self.x < self.width && y >= self.y && y - self.y < self.height
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct VisualOptions {
+    /// Largest per-channel difference for pixels to be considered equal
+    pub tolerance: u8,
+    /// Number of differing pixels allowed before the comparison fails
+    pub max_diff_pixels: usize,
+    /// Regions excluded from the comparison
+    pub ignore: Vec<Region>,
+}
+
+/// Outcome of a visual comparison.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct VisualDiff {
+    pub passed: bool,
+    /// No baseline existed, so the screenshot was stored as the new baseline
+    pub baseline_created: bool,
+    /// The images have different dimensions and were not compared
+    pub size_mismatch: bool,
+    pub width: u32,
+    pub height: u32,
+    pub diff_pixels: usize,
+    /// Differing pixels as a fraction of compared pixels
+    pub diff_ratio: f64,
+    /// Path of the written diff image, if any
+    pub diff_image: Option<PathBuf>,
+}
+
+/// An RGBA8 image.
+struct Image {
+    width: u32,
+    height: u32,
+    data: Vec<u8>,
+}
+
+impl Image {
+    fn decode(png: &[u8]) -> Result<Self, Error> {
+        let mut decoder = Decoder::new(png);
+        decoder.set_transformations(Transformations::EXPAND | Transformations::STRIP_16);
+        let (info, mut reader) = decoder.read_info()?;
+        let mut buf = vec![0; info.buffer_size()];
+        reader.next_frame(&mut buf)?;
+        let data = match reader.output_color_type().0 {
+            ColorType::RGBA => buf,
+            ColorType::RGB => buf
+                .chunks(3)
+                .flat_map(|p| vec![p[0], p[1], p[2], 255])
+                .collect(),
+            ColorType::GrayscaleAlpha => buf
+                .chunks(2)
+                .flat_map(|p| vec![p[0], p[0], p[0], p[1]])
+                .collect(),
+            ColorType::Grayscale => buf.iter().flat_map(|&p| vec![p, p, p, 255]).collect(),
+            ColorType::Indexed => failure::bail!("unexpanded indexed PNG"),
+        };
+        Ok(Self {
+            width: info.width,
+            height: info.height,
+            data,
+        })
+    }
+
+    fn encode(&self, path: &Path) -> Result<(), Error> {
+        self.write(BufWriter::new(File::create(path)?))
+    }
+
+    fn write<W: Write>(&self, w: W) -> Result<(), Error> {
+        let mut encoder = Encoder::new(w, self.width, self.height);
+        encoder.set_color(ColorType::RGBA);
+        encoder.set_depth(BitDepth::Eight);
+        encoder.write_header()?.write_image_data(&self.data)?;
+        Ok(())
+    }
+}
+
+/// Crops PNG data to the given rectangle, clamped to the image bounds.
+pub(crate) fn crop(png: &[u8], x: u32, y: u32, width: u32, height: u32) -> Result<Vec<u8>, Error> {
+    let img = Image::decode(png)?;
+    let x = x.min(img.width);
+    let y = y.min(img.height);
+    let width = width.min(img.width - x);
+    let height = height.min(img.height - y);
+    if width == 0 || height == 0 {
+        failure::bail!("element is outside of the screenshot");
+    }
+    let mut data = Vec::with_capacity((width * height * 4) as usize);
+    for row in y..y + height {
+        let start = ((row * img.width + x) * 4) as usize;
+        data.extend_from_slice(&img.data[start..start + (width * 4) as usize]);
+    }
+    let mut out = vec![];
+    Image {
+        width,
+        height,
+        data,
+    }
+    .write(&mut out)?;
+    Ok(out)
+}
+
+/// Compares `actual` PNG data against the PNG stored at `baseline`.
+///
+/// If `baseline` does not exist, `actual` is written there and the
+/// comparison passes. When `diff_path` is given and the images differ, an
+/// image is written there showing the baseline faded, differing pixels in
+/// red and ignored regions in blue.
+pub fn compare(
+    actual: &[u8],
+    baseline: &Path,
+    diff_path: Option<&Path>,
+    options: &VisualOptions,
+) -> Result<VisualDiff, Error> {
+    let actual_img = Image::decode(actual)?;
+    if !baseline.exists() {
+        if let Some(dir) = baseline.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(baseline, actual)?;
+        return Ok(VisualDiff {
+            passed: true,
+            baseline_created: true,
+            size_mismatch: false,
+            width: actual_img.width,
+            height: actual_img.height,
+            diff_pixels: 0,
+            diff_ratio: 0.0,
+            diff_image: None,
+        });
+    }
+    let baseline_img = Image::decode(&fs::read(baseline)?)?;
+    if (actual_img.width, actual_img.height) != (baseline_img.width, baseline_img.height) {
+        return Ok(VisualDiff {
+            passed: false,
+            baseline_created: false,
+            size_mismatch: true,
+            width: actual_img.width,
+            height: actual_img.height,
+            diff_pixels: 0,
+            diff_ratio: 1.0,
+            diff_image: None,
+        });
+    }
+
+    let (width, height) = (actual_img.width, actual_img.height);
+    let mut diff = Image {
+        width,
+        height,
+        data: vec![0; actual_img.data.len()],
+    };
+    let mut diff_pixels = 0;
+    let mut compared = 0;
+    for y in 0..height {
+        for x in 0..width {
+            let idx = ((y * width + x) * 4) as usize;
+            let a = &actual_img.data[idx..idx + 4];
+            let b = &baseline_img.data[idx..idx + 4];
+            let out = &mut diff.data[idx..idx + 4];
+            if options.ignore.iter().any(|r| r.contains(x, y)) {
+                out.copy_from_slice(&[0, 0, 255, 255]);
+                continue;
+            }
+            compared += 1;
+            let differs = a
+                .iter()
+                .zip(b)
+                .any(|(&a, &b)| (a as i16 - b as i16).abs() > options.tolerance as i16);
+            if differs {
+                diff_pixels += 1;
+                out.copy_from_slice(&[255, 0, 0, 255]);
+            } else {
+                let gray = ((b[0] as u16 + b[1] as u16 + b[2] as u16) / 3) as u8;
+                let faded = 255 - (255 - gray) / 4;
+                out.copy_from_slice(&[faded, faded, faded, 255]);
+            }
+        }
+    }
+
+    let diff_image = match diff_path {
+        Some(path) if diff_pixels > 0 => {
+            diff.encode(path)?;
+            Some(path.to_owned())
+        }
+        _ => None,
+    };
+    Ok(VisualDiff {
+        passed: diff_pixels <= options.max_diff_pixels,
+        baseline_created: false,
+        size_mismatch: false,
+        width,
+        height,
+        diff_pixels,
+        diff_ratio: if compared == 0 {
+            0.0
+        } else {
+            diff_pixels as f64 / compared as f64
+        },
+        diff_image,
+    })
+}
+
+impl WebDriver {
+    /// Takes a screenshot and compares it against `baseline`, see [`compare`].
+    pub async fn compare_screenshot(
+        &self,
+        baseline: &Path,
+        diff_path: Option<&Path>,
+        options: &VisualOptions,
+    ) -> Result<VisualDiff, Error> {
+        let png = self.screenshot().await?;
+        compare(&png, baseline, diff_path, options)
+    }
+}